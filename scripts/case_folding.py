@@ -0,0 +1,52 @@
+#!/usr/bin/env python3
+"""Generates src/case_folding.rs from Unicode's CaseFolding.txt.
+
+    python3 scripts/case_folding.py CaseFolding.txt > src/case_folding.rs
+
+Only the common (C) and simple (S) foldings are used. Characters that fold
+to the same character form an orbit, and each is mapped to the next one in
+its orbit in code point order, wrapping around to the first.
+"""
+
+import re
+import sys
+
+
+def main(path):
+    version = None
+    folds = {}
+    with open(path, encoding="utf-8") as data:
+        for line in data:
+            found = re.match(r"# CaseFolding-(\d+\.\d+\.\d+)\.txt", line)
+            if found:
+                version = found.group(1)
+            line = line.split("#", 1)[0].strip()
+            if not line:
+                continue
+            code, status, mapping = [field.strip() for field in line.split(";")[:3]]
+            if status in ("C", "S"):
+                folds[int(code, 16)] = int(mapping, 16)
+
+    orbits = {}
+    for code, folded in folds.items():
+        orbits.setdefault(folded, {folded}).add(code)
+
+    table = []
+    for orbit in orbits.values():
+        orbit = sorted(orbit)
+        table.extend(zip(orbit, orbit[1:] + orbit[:1]))
+    table.sort()
+
+    print("// Generated by scripts/case_folding.py from CaseFolding.txt, Unicode")
+    print("// {}, statuses C and S. Don't edit by hand.".format(version))
+    print()
+    print("/// Every character with simple case foldings, mapped to the next character")
+    print("/// that folds the same, in code point order and wrapping around.")
+    print("pub const CASE_FOLDING: &[(char, char)] = &[")
+    for code, other in table:
+        print("    ('\\u{{{:X}}}', '\\u{{{:X}}}'),".format(code, other))
+    print("];")
+
+
+if __name__ == "__main__":
+    main(sys.argv[1])
@@ -0,0 +1,278 @@
+use stream;
+use stream::{GBegin, GEnd, Group, Token};
+
+use graph::Assertion;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Repetition {
+    Maybe, // ?
+    Always, // +
+    Any, // *
+}
+
+/// A pattern as a tree, with the same meaning as its flat token stream.
+///
+/// Converting a token stream to an `Ast` and back gives the same tokens.
+/// The other way round, nested `Concat`s are flattened and a `Concat` with a
+/// single item becomes that item.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Ast {
+    /// `Literal::Char`
+    Literal(char),
+    /// Any other literal that matches a single character: `AnyChar`, `Range`
+    /// or a named class
+    Class(stream::Literal),
+    /// `StartOfLine`, `EndOfLine`, `StartOfText` and `EndOfText`
+    Assertion(Assertion),
+    Concat(Vec<Ast>),
+    /// An `Or` group, one entry per branch
+    Alternation(Vec<Ast>),
+    Repetition(Repetition, Box<Ast>),
+    /// A capture group
+    Group(Option<String>, Box<Ast>),
+    Not(Box<Ast>),
+    /// A `Complement` group
+    Complement(Box<Ast>),
+    /// An `And` group, one entry per operand
+    Intersection(Vec<Ast>),
+}
+
+struct Reader<'a> {
+    toks: &'a [Token],
+    pos: usize,
+}
+
+/// A sequence of one item is the item itself
+pub(crate) fn concat(mut items: Vec<Ast>) -> Ast {
+    if items.len() == 1 {
+        items.pop().unwrap()
+    } else {
+        Ast::Concat(items)
+    }
+}
+
+impl<'a> Reader<'a> {
+    fn next(&mut self) -> Result<&'a Token, String> {
+        let tok = self.toks.get(self.pos).ok_or("Token stream ended inside a group".to_string())?;
+        self.pos += 1;
+        Ok(tok)
+    }
+
+    /// Reads up to the `End(end)` closing the current group, returning one
+    /// tree per branch. Branches are separated by `delimiter`, which is
+    /// `OrDelimiter` or `AndDelimiter` for the groups that have them.
+    fn branches_until(&mut self, end: &GEnd, delimiter: Option<&Group>)
+        -> Result<Vec<Ast>, String> {
+        token_use!();
+        let mut branches = Vec::new();
+        let mut items = Vec::new();
+
+        loop {
+            let item = match *self.next()? {
+                Literal(Char(c)) => Ast::Literal(c),
+                Literal(StartOfLine) => Ast::Assertion(Assertion::StartOfLine),
+                Literal(EndOfLine) => Ast::Assertion(Assertion::EndOfLine),
+                Literal(StartOfText) => Ast::Assertion(Assertion::StartOfText),
+                Literal(EndOfText) => Ast::Assertion(Assertion::EndOfText),
+                Literal(ref lit) => Ast::Class(lit.clone()),
+                Grouping(ref group @ OrDelimiter) | Grouping(ref group @ AndDelimiter) => {
+                    if Some(group) != delimiter {
+                        return Err(format!("{:?} outside of its group in {:?}", group, end));
+                    }
+                    branches.push(concat(items));
+                    items = Vec::new();
+                    continue;
+                },
+                Grouping(End(ref kind)) if kind == end => {
+                    branches.push(concat(items));
+                    return Ok(branches);
+                },
+                Grouping(End(ref kind)) => return Err(format!("Unexpected end of {:?} group", kind)),
+                Grouping(Begin(ref kind)) => self.group(kind)?,
+            };
+            items.push(item);
+        }
+    }
+
+    fn sequence_until(&mut self, end: &GEnd) -> Result<Ast, String> {
+        let mut branches = self.branches_until(end, None)?;
+        Ok(branches.pop().unwrap())
+    }
+
+    fn group(&mut self, kind: &GBegin) -> Result<Ast, String> {
+        let ast = match *kind {
+            GBegin::Pat => return Err("Pat group inside a pattern".to_string()),
+            GBegin::Or => Ast::Alternation(self.branches_until(&GEnd::Or, Some(&Group::OrDelimiter))?),
+            GBegin::And => {
+                Ast::Intersection(self.branches_until(&GEnd::And, Some(&Group::AndDelimiter))?)
+            },
+            GBegin::Complement => Ast::Complement(Box::new(self.sequence_until(&GEnd::Complement)?)),
+            GBegin::Cap { ref name } => {
+                Ast::Group(name.clone(), Box::new(self.sequence_until(&GEnd::Cap)?))
+            },
+            GBegin::Not => Ast::Not(Box::new(self.sequence_until(&GEnd::Not)?)),
+            GBegin::Maybe => self.repetition(Repetition::Maybe, &GEnd::Maybe)?,
+            GBegin::Always => self.repetition(Repetition::Always, &GEnd::Always)?,
+            GBegin::Any => self.repetition(Repetition::Any, &GEnd::Any)?,
+        };
+        Ok(ast)
+    }
+
+    fn repetition(&mut self, kind: Repetition, end: &GEnd) -> Result<Ast, String> {
+        Ok(Ast::Repetition(kind, Box::new(self.sequence_until(end)?)))
+    }
+}
+
+/// An `Or` or `And` group with `delimiter` between its branches
+fn push_branches(toks: &mut Vec<Token>, begin: GBegin, delimiter: Group, end: GEnd,
+                 branches: &[Ast]) {
+    toks.push(Token::Grouping(Group::Begin(begin)));
+    for (i, branch) in branches.iter().enumerate() {
+        if i > 0 {
+            toks.push(Token::Grouping(delimiter.clone()));
+        }
+        branch.push_tokens(toks);
+    }
+    toks.push(Token::Grouping(Group::End(end)));
+}
+
+impl Ast {
+    /// Builds the tree for a whole token stream, from `Begin(Pat)` to
+    /// `End(Pat)`.
+    pub fn from_tokens(toks: &[Token]) -> Result<Ast, String> {
+        token_use!();
+        let mut reader = Reader { toks, pos: 0 };
+
+        match *reader.next()? {
+            Grouping(Begin(GBegin::Pat)) => (),
+            ref tok => return Err(format!("Token stream starts with {:?} instead of Pat", tok)),
+        }
+        let ast = reader.sequence_until(&GEnd::Pat)?;
+        if reader.pos != toks.len() {
+            return Err("Tokens after the end of the pattern".to_string());
+        }
+        Ok(ast)
+    }
+
+    pub fn from_string(pat: &str) -> Result<Ast, String> {
+        let parser = stream::TokenParser::from_string(pat)?;
+        Ast::from_tokens(parser.tokens())
+    }
+
+    /// The token stream for the tree, wrapped in `Pat`
+    pub fn to_tokens(&self) -> Vec<Token> {
+        let mut toks = Vec::new();
+        self.push_tokens(&mut toks);
+        stream::wrap(GBegin::Pat, GEnd::Pat, toks)
+    }
+
+    pub(crate) fn push_tokens(&self, toks: &mut Vec<Token>) {
+        token_use!();
+        let (begin, end, inner) = match *self {
+            Ast::Literal(c) => return toks.push(Literal(Char(c))),
+            Ast::Class(ref lit) => return toks.push(Literal(lit.clone())),
+            Ast::Assertion(Assertion::StartOfLine) => return toks.push(Literal(StartOfLine)),
+            Ast::Assertion(Assertion::EndOfLine) => return toks.push(Literal(EndOfLine)),
+            Ast::Assertion(Assertion::StartOfText) => return toks.push(Literal(StartOfText)),
+            Ast::Assertion(Assertion::EndOfText) => return toks.push(Literal(EndOfText)),
+            Ast::Concat(ref items) => {
+                for item in items {
+                    item.push_tokens(toks);
+                }
+                return;
+            },
+            Ast::Alternation(ref branches) => {
+                return push_branches(toks, GBegin::Or, OrDelimiter, GEnd::Or, branches);
+            },
+            Ast::Intersection(ref operands) => {
+                return push_branches(toks, GBegin::And, AndDelimiter, GEnd::And, operands);
+            },
+            Ast::Repetition(Repetition::Maybe, ref inner) => (GBegin::Maybe, GEnd::Maybe, inner),
+            Ast::Repetition(Repetition::Always, ref inner) => (GBegin::Always, GEnd::Always, inner),
+            Ast::Repetition(Repetition::Any, ref inner) => (GBegin::Any, GEnd::Any, inner),
+            Ast::Group(ref name, ref inner) => (GBegin::Cap { name: name.clone() }, GEnd::Cap, inner),
+            Ast::Not(ref inner) => (GBegin::Not, GEnd::Not, inner),
+            Ast::Complement(ref inner) => (GBegin::Complement, GEnd::Complement, inner),
+        };
+
+        toks.push(Grouping(Begin(begin)));
+        inner.push_tokens(toks);
+        toks.push(Grouping(End(end)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use ast::{Ast, Repetition};
+    use graph::Assertion;
+    use stream::{Token, TokenParser};
+
+    fn round_trip(toks: &[Token]) {
+        let ast = Ast::from_tokens(toks).unwrap();
+        assert_eq!(toks, &ast.to_tokens()[..]);
+    }
+
+    #[test]
+    fn structure() {
+        use stream::Literal::Digit;
+        let ast = Ast::from_string(r"^a(:<n>b|\d)*$").unwrap();
+        let expected = Ast::Concat(vec![
+            Ast::Assertion(Assertion::StartOfLine),
+            Ast::Literal('a'),
+            Ast::Repetition(Repetition::Any, Box::new(
+                Ast::Group(Some("n".to_string()), Box::new(
+                    Ast::Alternation(vec![Ast::Literal('b'), Ast::Class(Digit)]),
+                )),
+            )),
+            Ast::Assertion(Assertion::EndOfLine),
+        ]);
+        assert_eq!(expected, ast);
+    }
+
+    #[test]
+    fn single_and_empty_sequences() {
+        assert_eq!(Ast::Literal('a'), Ast::from_string("a").unwrap());
+        assert_eq!(Ast::Concat(vec![]), Ast::from_string("").unwrap());
+        assert_eq!(Ast::Alternation(vec![Ast::Literal('a'), Ast::Concat(vec![])]),
+            Ast::from_string("a|").unwrap());
+    }
+
+    #[test]
+    fn round_trips() {
+        let patterns = ["abcd", "a(bc)?d", "a(:<G>b|c|d)+", "a|b|c", "ab[^c-e]d",
+            r"[[:alpha:]\]x-]", r"\W\d\S", "a**", "(a(b)|c)|d", "^.$", "a||"];
+        for pat in &patterns {
+            round_trip(TokenParser::from_string(pat).unwrap().tokens());
+        }
+        round_trip(TokenParser::from_glob("src/**/{a,b}[!x]*").unwrap().tokens());
+        round_trip(TokenParser::from_similar_to("(a|b%){2,3}_", None).unwrap().tokens());
+    }
+
+    #[test]
+    fn malformed_streams() {
+        use stream;
+        token_use!();
+        assert!(Ast::from_tokens(&[]).is_err());
+        assert!(Ast::from_tokens(&[Literal(Char('a'))]).is_err());
+        assert!(Ast::from_tokens(&[Grouping(Begin(GBegin::Pat)),
+            Grouping(Begin(GBegin::Any)), Grouping(End(GEnd::Maybe)),
+            Grouping(End(GEnd::Pat))]).is_err());
+        assert!(Ast::from_tokens(&[Grouping(Begin(GBegin::Pat)),
+            Literal(Char('a')), Grouping(OrDelimiter), Literal(Char('b')),
+            Grouping(End(GEnd::Pat))]).is_err());
+        assert!(Ast::from_tokens(&[Grouping(Begin(GBegin::Pat)),
+            Grouping(End(GEnd::Pat)), Literal(Char('a'))]).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn parsed_patterns_round_trip(pat in r"[ab.^$|()*+?\[\]\-]{0,12}") {
+            if let Ok(parser) = TokenParser::from_string(&pat) {
+                let ast = Ast::from_tokens(parser.tokens()).unwrap();
+                prop_assert_eq!(parser.tokens(), ast.to_tokens());
+            }
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use std::char;
+use std::cmp;
+
+use case_folding::CASE_FOLDING;
+
+/// A set of characters, stored as sorted, non-overlapping and non-adjacent
+/// inclusive ranges.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct CharSet {
+    ranges: Vec<(char, char)>,
+}
+
+/// The character after `c`, skipping the surrogate gap
+pub fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        _ => char::from_u32(c as u32 + 1),
+    }
+}
+
+/// The character before `c`, skipping the surrogate gap
+pub fn prev_char(c: char) -> Option<char> {
+    match c {
+        '\u{E000}' => Some('\u{D7FF}'),
+        '\0' => None,
+        _ => char::from_u32(c as u32 - 1),
+    }
+}
+
+impl CharSet {
+    pub fn new() -> CharSet {
+        CharSet::default()
+    }
+
+    pub fn any() -> CharSet {
+        CharSet::range('\0', char::MAX)
+    }
+
+    pub fn single(c: char) -> CharSet {
+        CharSet::range(c, c)
+    }
+
+    pub fn range(begin: char, end: char) -> CharSet {
+        CharSet::from_ranges(vec![(begin, end)])
+    }
+
+    /// Builds a set from ranges in any order. Ranges whose begin is after
+    /// their end are ignored.
+    pub fn from_ranges<I>(ranges: I) -> CharSet
+        where I: IntoIterator<Item = (char, char)> {
+        let mut sorted: Vec<(char, char)> = ranges.into_iter()
+            .filter(|&(begin, end)| begin <= end)
+            .collect();
+        sorted.sort();
+
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(sorted.len());
+        for (begin, end) in sorted {
+            if let Some(last) = merged.last_mut() {
+                if next_char(last.1).is_none_or(|after| begin <= after) {
+                    last.1 = cmp::max(last.1, end);
+                    continue;
+                }
+            }
+            merged.push((begin, end));
+        }
+
+        CharSet { ranges: merged }
+    }
+
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        self.ranges.binary_search_by(|&(begin, end)| {
+            if end < c {
+                cmp::Ordering::Less
+            } else if begin > c {
+                cmp::Ordering::Greater
+            } else {
+                cmp::Ordering::Equal
+            }
+        }).is_ok()
+    }
+
+    /// The number of characters in the set
+    pub fn len(&self) -> u32 {
+        self.ranges.iter()
+            .map(|&(begin, end)| {
+                let size = end as u32 - begin as u32 + 1;
+                if begin <= '\u{D7FF}' && end >= '\u{E000}' {
+                    size - 0x800
+                } else {
+                    size
+                }
+            })
+            .sum()
+    }
+
+    /// The `index`th character of the set, counting from 0 in order
+    pub fn nth(&self, index: u32) -> Option<char> {
+        let mut index = index;
+        for &(begin, end) in &self.ranges {
+            let size = CharSet::range(begin, end).len();
+            if index < size {
+                let c = begin as u32 + index;
+                // Stepping over the surrogates if they're in the way
+                let c = if begin <= '\u{D7FF}' && c > 0xD7FF { c + 0x800 } else { c };
+                return char::from_u32(c);
+            }
+            index -= size;
+        }
+        None
+    }
+
+    pub fn union(&self, other: &CharSet) -> CharSet {
+        CharSet::from_ranges(self.ranges.iter().chain(other.ranges.iter()).cloned())
+    }
+
+    pub fn negate(&self) -> CharSet {
+        let mut ranges = Vec::with_capacity(self.ranges.len() + 1);
+        let mut begin = Some('\0');
+
+        for &(low, high) in &self.ranges {
+            if let Some(b) = begin {
+                if let Some(before) = prev_char(low) {
+                    if b <= before {
+                        ranges.push((b, before));
+                    }
+                }
+            }
+            begin = next_char(high);
+        }
+        if let Some(b) = begin {
+            ranges.push((b, char::MAX));
+        }
+
+        CharSet { ranges }
+    }
+
+    pub fn intersect(&self, other: &CharSet) -> CharSet {
+        self.negate().union(&other.negate()).negate()
+    }
+
+    pub fn difference(&self, other: &CharSet) -> CharSet {
+        self.intersect(&other.negate())
+    }
+
+    /// Adds every character that is equal to a member of the set under
+    /// Unicode simple case folding, the `C` and `S` entries of
+    /// `CaseFolding.txt`.
+    ///
+    /// Only the cased characters inside each range are looked up, so large
+    /// ranges stay cheap.
+    pub fn case_fold(&self) -> CharSet {
+        let mut ranges = self.ranges.clone();
+
+        for &(begin, end) in &self.ranges {
+            let first = CASE_FOLDING.partition_point(|&(c, _)| c < begin);
+            for &(c, mut other) in CASE_FOLDING[first..].iter().take_while(|&&(c, _)| c <= end) {
+                while other != c {
+                    ranges.push((other, other));
+                    other = fold_next(other);
+                }
+            }
+        }
+
+        CharSet::from_ranges(ranges)
+    }
+}
+
+fn fold_next(c: char) -> char {
+    match CASE_FOLDING.binary_search_by_key(&c, |&(from, _)| from) {
+        Ok(index) => CASE_FOLDING[index].1,
+        Err(_) => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use case_folding::CASE_FOLDING;
+    use charset::CharSet;
+
+    #[test]
+    fn from_ranges_merges() {
+        let set = CharSet::from_ranges(vec![('d', 'f'), ('a', 'b'), ('c', 'c'), ('x', 'z')]);
+        assert_eq!(&[('a', 'f'), ('x', 'z')], set.ranges());
+    }
+
+    #[test]
+    fn nth() {
+        let set = CharSet::from_ranges(vec![('a', 'c'), ('x', 'x'), ('\u{D7FE}', '\u{E001}')]);
+        let all: Vec<char> = (0..set.len()).map(|i| set.nth(i).unwrap()).collect();
+        assert_eq!(vec!['a', 'b', 'c', 'x', '\u{D7FE}', '\u{D7FF}', '\u{E000}', '\u{E001}'], all);
+        assert_eq!(None, set.nth(set.len()));
+    }
+
+    #[test]
+    fn negate_twice() {
+        let set = CharSet::from_ranges(vec![('\0', 'a'), ('q', '\u{D7FF}')]);
+        let negated = set.negate();
+        assert_eq!(&[('b', 'p'), ('\u{E000}', ::std::char::MAX)], negated.ranges());
+        assert_eq!(set, negated.negate());
+        assert_eq!(CharSet::any(), CharSet::new().negate());
+    }
+
+    #[test]
+    fn intersect_and_len() {
+        let a = CharSet::range('a', 'm');
+        let b = CharSet::range('h', 'z');
+        assert_eq!(CharSet::range('h', 'm'), a.intersect(&b));
+        assert_eq!(6, a.intersect(&b).len());
+        assert_eq!(0x10F800, CharSet::any().len());
+    }
+
+    #[test]
+    fn fold_ascii_range() {
+        let folded = CharSet::range('a', 'f').case_fold();
+        assert_eq!(&[('A', 'F'), ('a', 'f')], folded.ranges());
+    }
+
+    #[test]
+    fn fold_beyond_ascii() {
+        let folded = CharSet::single('k').case_fold();
+        assert!(folded.contains('K'));
+        assert!(folded.contains('\u{212A}')); // Kelvin sign
+        assert_eq!(3, folded.len());
+
+        let sigma = CharSet::single('\u{03C3}').case_fold();
+        assert!(sigma.contains('\u{03A3}'));
+        assert!(sigma.contains('\u{03C2}')); // final sigma
+    }
+
+    #[test]
+    fn fold_without_turkic_i() {
+        assert_eq!(&[('I', 'I'), ('i', 'i')], CharSet::single('i').case_fold().ranges());
+        assert_eq!(1, CharSet::single('\u{131}').case_fold().len());
+        assert_eq!(1, CharSet::single('\u{130}').case_fold().len());
+    }
+
+    #[test]
+    fn fold_simple_entries() {
+        // `S` entries, whose full foldings are longer
+        assert_eq!(&[('\u{DF}', '\u{DF}'), ('\u{1E9E}', '\u{1E9E}')],
+            CharSet::single('\u{DF}').case_fold().ranges());
+        assert!(CharSet::single('s').case_fold().contains('\u{17F}')); // long s
+        // Only `F` entries, so nothing to fold to
+        assert_eq!(1, CharSet::single('\u{149}').case_fold().len());
+    }
+
+    #[test]
+    fn fold_table_orbits() {
+        assert!(CASE_FOLDING.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        for &(c, other) in CASE_FOLDING {
+            assert_ne!(c, other);
+            assert!(CASE_FOLDING.binary_search_by_key(&other, |&(from, _)| from).is_ok());
+        }
+    }
+}
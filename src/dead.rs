@@ -0,0 +1,250 @@
+//! Finds patterns that can never match or that match every text whole, and
+//! the parts of patterns that can't make a difference.
+//!
+//! A part is dead if it matches nothing, like `a^b` or a `[^...]` that
+//! excludes every character, or if it's an `Or` branch that only matches
+//! what earlier branches already match, like `foo` in `[a-z]+|foo`.
+//!
+//! Parts are checked through automata built from them alone, taking `^` and
+//! `$` to hold where they could. So nothing is reported dead that isn't,
+//! but a part can be dead only because of what's around it and go unnoticed,
+//! like `^` in `a(^|b)`. Earlier branches with `^` or `$` are left out of
+//! comparisons for the same reason.
+//!
+//! ```
+//! use toy_regex::dead::{analyze, Reason};
+//! use toy_regex::graph::Options;
+//! use toy_regex::stream::TokenParser;
+//!
+//! let parser = TokenParser::from_string("id-([0-9]+|42|x^y)").unwrap();
+//! let analysis = analyze(&parser, &Options::new()).unwrap();
+//! assert!(!analysis.matches_nothing && !analysis.matches_everything);
+//! assert_eq!(11..13, analysis.dead[0].span);
+//! assert_eq!(Reason::Subsumed, analysis.dead[0].reason);
+//! assert_eq!(14..17, analysis.dead[1].span);
+//! assert_eq!(Reason::MatchesNothing, analysis.dead[1].reason);
+//! ```
+
+use std::ops::Range;
+
+use ast::Ast;
+use dfa::Dfa;
+use graph::{Graph, Options};
+use stream::{GBegin, GEnd, Group, Literal, Span, Token, TokenParser};
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Analysis {
+    /// No text contains a match
+    pub matches_nothing: bool,
+    /// Every text matches as a whole
+    pub matches_everything: bool,
+    /// The outermost dead parts, in order
+    pub dead: Vec<Dead>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Dead {
+    pub span: Span,
+    pub reason: Reason,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Reason {
+    /// No text matches it
+    MatchesNothing,
+    /// An `Or` branch whose matches all match an earlier branch
+    Subsumed,
+}
+
+/// Checks `parser`'s pattern compiled with `options`. `~(...)` and `&` are
+/// allowed.
+pub fn analyze(parser: &TokenParser, options: &Options) -> Result<Analysis, String> {
+    let graph = Graph::compile(&Ast::from_tokens(parser.tokens())?, options, true)?;
+    let matches_nothing = !Dfa::new(&graph)?.live()[0];
+    let matches_everything = !Dfa::anchored(&graph)?.complement().live()[0];
+
+    let checker = Checker { toks: parser.tokens(), options };
+    let mut found: Vec<(Range<usize>, Reason)> = Vec::new();
+    for (group, branches) in groups(parser.tokens()) {
+        if parser.tokens()[group.start] != Token::Grouping(Group::Begin(GBegin::Pat))
+            && checker.is_empty(group.clone())? {
+            found.push((group.clone(), Reason::MatchesNothing));
+        }
+        if parser.tokens()[group.start] != Token::Grouping(Group::Begin(GBegin::Or)) {
+            continue;
+        }
+
+        // The union of the earlier branches that mean the same anywhere
+        let mut earlier: Option<Dfa> = None;
+        for branch in branches {
+            let dfa = checker.dfa(branch.clone())?;
+            if !dfa.live()[0] {
+                found.push((branch, Reason::MatchesNothing));
+                continue;
+            }
+            if let Some(ref earlier) = earlier {
+                if !dfa.difference(earlier)?.live()[0] {
+                    found.push((branch, Reason::Subsumed));
+                    continue;
+                }
+            }
+            if !has_assertions(&parser.tokens()[branch]) {
+                earlier = Some(match earlier {
+                    Some(earlier) => earlier.union(&dfa)?,
+                    None => dfa,
+                });
+            }
+        }
+    }
+
+    // Only the outermost, since everything in a dead part is dead too
+    found.sort_by_key(|(range, _)| (range.start, !range.end));
+    let mut dead: Vec<Dead> = Vec::new();
+    let mut covered = 0..0;
+    for (range, reason) in found {
+        if covered.start <= range.start && range.end <= covered.end && !covered.is_empty() {
+            continue;
+        }
+        dead.push(Dead { span: span(parser, &range), reason });
+        covered = range;
+    }
+
+    Ok(Analysis { matches_nothing, matches_everything, dead })
+}
+
+/// Every group, as the range of its tokens from `Begin` to `End`, with the
+/// ranges of its branches or operands
+fn groups(toks: &[Token]) -> Vec<(Range<usize>, Vec<Range<usize>>)> {
+    let mut open: Vec<(usize, Vec<Range<usize>>, usize)> = Vec::new();
+    let mut groups = Vec::new();
+    for (index, tok) in toks.iter().enumerate() {
+        match *tok {
+            Token::Grouping(Group::Begin(_)) => open.push((index, Vec::new(), index + 1)),
+            Token::Grouping(Group::OrDelimiter) | Token::Grouping(Group::AndDelimiter) => {
+                let top = open.last_mut().unwrap();
+                top.1.push(top.2..index);
+                top.2 = index + 1;
+            },
+            Token::Grouping(Group::End(_)) => {
+                let (begin, mut branches, start) = open.pop().unwrap();
+                branches.push(start..index);
+                groups.push((begin..index + 1, branches));
+            },
+            Token::Literal(_) => (),
+        }
+    }
+    groups
+}
+
+fn has_assertions(toks: &[Token]) -> bool {
+    toks.iter().any(|tok| {
+        matches!(*tok, Token::Literal(Literal::StartOfLine) | Token::Literal(Literal::EndOfLine)
+            | Token::Literal(Literal::StartOfText) | Token::Literal(Literal::EndOfText))
+    })
+}
+
+/// The source that `range`'s tokens came from. An empty branch is the
+/// empty span where it would be.
+fn span(parser: &TokenParser, range: &Range<usize>) -> Span {
+    let spans = parser.spans();
+    if range.is_empty() {
+        // After the delimiter before, or before the one after
+        let at = match parser.tokens()[range.start - 1] {
+            Token::Grouping(Group::Begin(_)) => spans[range.end].start,
+            _ => spans[range.start - 1].end,
+        };
+        return at..at;
+    }
+    let start = spans[range.clone()].iter().map(|span| span.start).min().unwrap();
+    let end = spans[range.clone()].iter().map(|span| span.end).max().unwrap();
+    start..end
+}
+
+struct Checker<'a> {
+    toks: &'a [Token],
+    options: &'a Options,
+}
+
+impl<'a> Checker<'a> {
+    /// The automaton matching the whole of texts that the tokens in `range`
+    /// match alone
+    fn dfa(&self, range: Range<usize>) -> Result<Dfa, String> {
+        let mut toks = Vec::with_capacity(range.len() + 2);
+        toks.push(Token::Grouping(Group::Begin(GBegin::Pat)));
+        toks.extend_from_slice(&self.toks[range]);
+        toks.push(Token::Grouping(Group::End(GEnd::Pat)));
+        Ok(Dfa::anchored(&Graph::compile(&Ast::from_tokens(&toks)?, self.options, true)?)?)
+    }
+
+    fn is_empty(&self, range: Range<usize>) -> Result<bool, String> {
+        Ok(!self.dfa(range)?.live()[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ast::Ast;
+    use dead::{analyze, Analysis, Dead, Reason};
+    use graph::Options;
+    use stream::{Literal, ParserConfig, TokenParser};
+
+    fn analysis(pat: &str) -> Analysis {
+        let mut config = ParserConfig::new();
+        config.operators(true);
+        analyze(&TokenParser::from_string_with(pat, &config).unwrap(), &Options::new()).unwrap()
+    }
+
+    fn dead(pat: &str) -> Vec<(::std::ops::Range<usize>, Reason)> {
+        analysis(pat).dead.into_iter().map(|Dead { span, reason }| (span, reason)).collect()
+    }
+
+    #[test]
+    fn matching_nothing() {
+        assert!(analysis("a^b").matches_nothing);
+        assert!(analysis("a&b").matches_nothing);
+        let everything = format!("[^\0-{}]", char::MAX);
+        assert!(analysis(&everything).matches_nothing);
+        assert_eq!(vec![(1..10, Reason::MatchesNothing)], dead(&format!("x{}?", everything)));
+
+        // \s and \S together, which brackets can't say
+        let whitespace = Ast::Class(Literal::Whitespace);
+        let ast = Ast::Not(Box::new(Ast::Alternation(vec![whitespace.clone(),
+            Ast::Not(Box::new(whitespace))])));
+        let parser = TokenParser::from_tokens(ast.to_tokens()).unwrap();
+        assert!(analyze(&parser, &Options::new()).unwrap().matches_nothing);
+
+        assert!(!analysis("a").matches_nothing);
+        assert!(!analysis("").matches_nothing);
+    }
+
+    #[test]
+    fn matching_everything() {
+        for pat in &["~(a)|a", "(a|[^a])*", "~(b)|b*"] {
+            assert!(analysis(pat).matches_everything, "{}", pat);
+        }
+        // Each is found in every text, but doesn't match every text whole
+        for pat in &["", "a*", "^", "$", "x|y?", "a", "^$", "a+"] {
+            assert!(!analysis(pat).matches_everything, "{}", pat);
+        }
+    }
+
+    #[test]
+    fn dead_parts() {
+        assert_eq!(vec![(4..7, Reason::MatchesNothing)], dead("x(y|a^b)"));
+        assert_eq!(vec![(1..6, Reason::MatchesNothing)], dead("x(a^b)*"));
+        assert_eq!(vec![(7..10, Reason::Subsumed)], dead("[a-z]+|foo"));
+        assert_eq!(vec![(4..8, Reason::Subsumed)], dead("a|b|[ab]"));
+        assert_eq!(vec![(4..5, Reason::Subsumed)], dead("[a-zq]"));
+        assert_eq!(vec![(4..4, Reason::Subsumed)], dead("(a?|)"));
+        assert_eq!(vec![(3..3, Reason::Subsumed)], dead("a||"));
+        assert_eq!(Vec::<(::std::ops::Range<usize>, Reason)>::new(), dead("ab|abc|a[bc]d"));
+    }
+
+    #[test]
+    fn assertions_are_context() {
+        // `^a` only matches at the start, so `a` is still needed
+        assert!(dead("x|^a|a").is_empty());
+        // But `^a` never matches anything `a` doesn't
+        assert_eq!(vec![(2..4, Reason::Subsumed)], dead("a|^a"));
+    }
+}
@@ -0,0 +1,735 @@
+//! Deterministic automata built from a `Graph`, and questions about the
+//! strings patterns accept.
+//!
+//! A `Dfa` made with `Dfa::new` accepts a text when `Graph::is_match` does,
+//! i.e. when a match is found anywhere in it. One made with `Dfa::anchored`
+//! only accepts texts that the pattern matches as a whole. `^` and `$` keep
+//! their meaning under the graph's `Options` in both.
+//!
+//! Patterns with `~(...)` and `&` (see `ParserConfig::operators`) can't be
+//! compiled to a `Graph`, but `Dfa::from_ast` and friends accept them. Each
+//! operand is matched as a whole, and can't contain `^` or `$`:
+//!
+//! ```
+//! use toy_regex::dfa::Dfa;
+//!
+//! // Lines of letters without "if" in them
+//! let dfa = Dfa::from_string("^(~(.*if.*)&[a-z]*)$").unwrap();
+//! assert!(dfa.is_match("elsewhere"));
+//! assert!(!dfa.is_match("iffy"));
+//! ```
+//!
+//! ```
+//! use toy_regex::dfa::{equivalent, is_subset};
+//! use toy_regex::graph::Graph;
+//!
+//! let old = Graph::from_string("^[a-z][a-z0-9]*$").unwrap();
+//! let new = Graph::from_string("^[a-z]([a-z]|[[:digit:]])*$").unwrap();
+//! assert_eq!(None, equivalent(&old, &new).unwrap());
+//!
+//! let stricter = Graph::from_string("^[a-z]+$").unwrap();
+//! assert_eq!(None, is_subset(&stricter, &old).unwrap());
+//! assert_eq!("a0", is_subset(&old, &stricter).unwrap().unwrap().text);
+//! ```
+//!
+//! Building an automaton can take a number of states exponential in the
+//! size of the pattern, as `(a|b)*a(a|b)(a|b)...` does, so construction gives
+//! up past `STATE_LIMIT` states, or the limit given to the `_limited`
+//! constructors, with a `TooLarge` error.
+//!
+//! Automata can also be combined into new matchers:
+//!
+//! ```
+//! use toy_regex::dfa::Dfa;
+//! use toy_regex::graph::Graph;
+//!
+//! let identifier = Dfa::new(&Graph::from_string("^[a-z_][a-z0-9_]*$").unwrap()).unwrap();
+//! let reserved = Dfa::new(&Graph::from_string("^(if|else|while)$").unwrap()).unwrap();
+//! let name = identifier.difference(&reserved).unwrap();
+//! assert!(name.is_match("iffy"));
+//! assert!(!name.is_match("while"));
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+
+use ast::Ast;
+use charset::{next_char, prev_char, CharSet};
+use graph::{Graph, Options, State};
+use stream::{ParserConfig, TokenParser};
+
+/// The transitions out of a state: ranges that together cover every
+/// character, in order, each with its target
+type Edges = Vec<(char, char, usize)>;
+
+#[derive(Clone, PartialEq, Debug)]
+struct DState {
+    accepting: bool,
+    edges: Edges,
+}
+
+/// A complete deterministic automaton. State 0 is the start, and every state
+/// has a transition on every character.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Dfa {
+    states: Vec<DState>,
+}
+
+/// The most states an automaton is built with, unless a limit is given
+pub const STATE_LIMIT: usize = 100_000;
+
+/// Building an automaton was given up because it needed more than `limit`
+/// states
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TooLarge {
+    pub limit: usize,
+}
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Automaton needs more than {} states", self.limit)
+    }
+}
+
+impl Error for TooLarge {}
+
+impl From<TooLarge> for String {
+    fn from(err: TooLarge) -> String {
+        err.to_string()
+    }
+}
+
+/// A string that one pattern accepts and the other doesn't
+#[derive(Clone, PartialEq, Debug)]
+pub struct Counterexample {
+    pub text: String,
+    /// Whether it is the first pattern that accepts `text`
+    pub accepted_by_a: bool,
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (yes, no) = if self.accepted_by_a { ("first", "second") } else { ("second", "first") };
+        write!(f, "{:?} is accepted by the {} pattern but not the {}", self.text, yes, no)
+    }
+}
+
+/// Checks that `a` and `b` accept the same texts: `None` if they do, or else
+/// one of the shortest texts that only one of them accepts
+pub fn equivalent(a: &Graph, b: &Graph) -> Result<Option<Counterexample>, TooLarge> {
+    let (a, b) = (Dfa::new(a)?, Dfa::new(b)?);
+    let text = a.product(&b, STATE_LIMIT, |a, b| a != b)?.shortest();
+    Ok(text.map(|text| Counterexample { accepted_by_a: a.is_match(&text), text }))
+}
+
+/// Checks that every text `a` accepts is also accepted by `b`: `None` if so,
+/// or else one of the shortest texts that isn't
+pub fn is_subset(a: &Graph, b: &Graph) -> Result<Option<Counterexample>, TooLarge> {
+    let (a, b) = (Dfa::new(a)?, Dfa::new(b)?);
+    let text = a.difference(&b)?.shortest();
+    Ok(text.map(|text| Counterexample { text, accepted_by_a: true }))
+}
+
+/// What `^` and `$` can tell about a neighbouring character: only whether it
+/// is missing, `\n`, `\r` or something else
+fn context(c: Option<char>) -> Option<char> {
+    match c {
+        None | Some('\n') | Some('\r') => c,
+        Some(_) => Some('x'),
+    }
+}
+
+/// The most readable character from `lo..=hi`, with its rank: lower is
+/// more readable
+pub(crate) fn pick(lo: char, hi: char) -> (usize, char) {
+    let readable = [('a', 'z'), ('A', 'Z'), ('0', '9'), (' ', '~')];
+    for (rank, &(a, b)) in readable.iter().enumerate() {
+        if lo <= b && a <= hi {
+            return (rank, ::std::cmp::max(lo, a));
+        }
+    }
+    (readable.len(), lo)
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    /// The graph states that have consumed the last character, and its
+    /// context
+    Threads(Vec<usize>, Option<char>),
+    /// A match was found, so every continuation is accepted too
+    Matched,
+}
+
+struct Builder<'a> {
+    graph: &'a Graph,
+    search: bool,
+    /// Ranges of characters that every set in the graph treats alike
+    intervals: Vec<(char, char)>,
+    ids: HashMap<Key, usize>,
+    keys: Vec<Key>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(graph: &'a Graph, search: bool) -> Builder<'a> {
+        let mut starts = vec!['\0', '\n', '\x0B', '\r', '\x0E'];
+        for state in graph.states() {
+            if let State::Char(ref set, _) = *state {
+                for &(begin, end) in set.ranges() {
+                    starts.push(begin);
+                    starts.extend(next_char(end));
+                }
+            }
+        }
+        starts.sort();
+        starts.dedup();
+
+        let mut intervals = Vec::with_capacity(starts.len());
+        for (i, &begin) in starts.iter().enumerate() {
+            let end = match starts.get(i + 1) {
+                Some(&next) => prev_char(next).unwrap(),
+                None => ::std::char::MAX,
+            };
+            intervals.push((begin, end));
+        }
+
+        Builder { graph, search, intervals, ids: HashMap::new(), keys: Vec::new() }
+    }
+
+    fn id(&mut self, key: Key) -> usize {
+        if let Some(&id) = self.ids.get(&key) {
+            return id;
+        }
+        self.keys.push(key.clone());
+        self.ids.insert(key, self.keys.len() - 1);
+        self.keys.len() - 1
+    }
+
+    /// `kernel` and everything reachable from it without consuming input,
+    /// between `prev` and `next`
+    fn closure(&self, kernel: &[usize], prev: Option<char>, next: Option<char>) -> Vec<usize> {
+        let states = self.graph.states();
+        let mut seen = vec![false; states.len()];
+        let mut stack = kernel.to_vec();
+        let mut reached = Vec::new();
+
+        while let Some(state) = stack.pop() {
+            if seen[state] {
+                continue;
+            }
+            seen[state] = true;
+            reached.push(state);
+            match states[state] {
+                State::Split(a, b) => {
+                    stack.push(b);
+                    stack.push(a);
+                },
+                State::Assert(assertion, next_state) => {
+                    if self.graph.options().assertion_holds(assertion, prev, next) {
+                        stack.push(next_state);
+                    }
+                },
+                State::Char(..) | State::Match => (),
+            }
+        }
+        reached
+    }
+
+    fn has_match(&self, states: &[usize]) -> bool {
+        states.iter().any(|&state| self.graph.states()[state] == State::Match)
+    }
+
+    fn step(&mut self, kernel: &[usize], prev: Option<char>, c: char) -> usize {
+        let reached = self.closure(kernel, prev, Some(c));
+        if self.search && self.has_match(&reached) {
+            return self.id(Key::Matched);
+        }
+
+        let mut next = Vec::new();
+        for state in reached {
+            if let State::Char(ref set, target) = self.graph.states()[state] {
+                if set.contains(c) {
+                    next.push(target);
+                }
+            }
+        }
+        if self.search {
+            next.push(self.graph.start());
+        }
+        next.sort();
+        next.dedup();
+
+        // Every dead state is the same one
+        let context = if next.is_empty() { None } else { context(Some(c)) };
+        self.id(Key::Threads(next, context))
+    }
+
+    /// The automaton, unless it has more than `limit` states
+    fn build(mut self, limit: usize) -> Result<Dfa, TooLarge> {
+        let mut states = Vec::new();
+        self.id(Key::Threads(vec![self.graph.start()], None));
+
+        while states.len() < self.keys.len() {
+            if self.keys.len() > limit {
+                return Err(TooLarge { limit });
+            }
+            let state = match self.keys[states.len()].clone() {
+                Key::Matched => DState {
+                    accepting: true,
+                    edges: vec![('\0', ::std::char::MAX, states.len())],
+                },
+                Key::Threads(kernel, prev) => {
+                    let accepting = self.has_match(&self.closure(&kernel, prev, None));
+                    let mut edges: Edges = Vec::new();
+                    for (begin, end) in self.intervals.clone() {
+                        let target = self.step(&kernel, prev, begin);
+                        push_edge(&mut edges, begin, end, target);
+                    }
+                    DState { accepting, edges }
+                },
+            };
+            states.push(state);
+        }
+
+        Ok(Dfa { states })
+    }
+}
+
+/// Adds a transition after the last one in `edges`, merging them if they
+/// lead to the same state
+fn push_edge(edges: &mut Edges, begin: char, end: char, target: usize) {
+    if let Some(last) = edges.last_mut() {
+        if last.2 == target {
+            last.1 = end;
+            return;
+        }
+    }
+    edges.push((begin, end, target));
+}
+
+impl Dfa {
+    /// Accepts the texts `graph.is_match` is true for, giving up past
+    /// `STATE_LIMIT` states
+    pub fn new(graph: &Graph) -> Result<Dfa, TooLarge> {
+        Dfa::new_limited(graph, STATE_LIMIT)
+    }
+
+    /// Like `new`, giving up past `limit` states
+    pub fn new_limited(graph: &Graph, limit: usize) -> Result<Dfa, TooLarge> {
+        Builder::new(graph, true).build(limit)
+    }
+
+    /// Accepts the texts that `graph` matches from start to end, giving up
+    /// past `STATE_LIMIT` states
+    pub fn anchored(graph: &Graph) -> Result<Dfa, TooLarge> {
+        Dfa::anchored_limited(graph, STATE_LIMIT)
+    }
+
+    /// Like `anchored`, giving up past `limit` states
+    pub fn anchored_limited(graph: &Graph, limit: usize) -> Result<Dfa, TooLarge> {
+        Builder::new(graph, false).build(limit)
+    }
+
+    /// Like `new`, but `ast` may contain `Complement` and `And` groups
+    pub fn from_ast(ast: &Ast, options: &Options) -> Result<Dfa, String> {
+        Ok(Dfa::new(&Graph::compile(ast, options, true)?)?)
+    }
+
+    /// Like `from_ast`, for a parsed pattern
+    pub fn from_stream(parser: TokenParser, options: &Options) -> Result<Dfa, String> {
+        Dfa::from_ast(&Ast::from_tokens(parser.tokens())?, options)
+    }
+
+    /// Like `from_stream`, parsing `pat` as a native pattern with
+    /// `ParserConfig::operators` set, so `~(...)` and `&` are available, and
+    /// default options
+    pub fn from_string(pat: &str) -> Result<Dfa, String> {
+        let mut config = ParserConfig::new();
+        config.operators(true);
+        Dfa::from_stream(TokenParser::from_string_with(pat, &config)?, &Options::default())
+    }
+
+    /// The number of states, including the dead state if there is one
+    pub fn state_count(&self) -> usize {
+        self.states.len()
+    }
+
+    fn target(&self, state: usize, c: char) -> usize {
+        let edges = &self.states[state].edges;
+        let index = edges.binary_search_by(|&(begin, end, _)| {
+            if end < c {
+                ::std::cmp::Ordering::Less
+            } else if begin > c {
+                ::std::cmp::Ordering::Greater
+            } else {
+                ::std::cmp::Ordering::Equal
+            }
+        });
+        edges[index.unwrap()].2
+    }
+
+    pub(crate) fn is_accepting(&self, state: usize) -> bool {
+        self.states[state].accepting
+    }
+
+    /// The transitions out of `state`, in character order
+    pub(crate) fn edges(&self, state: usize) -> &[(char, char, usize)] {
+        &self.states[state].edges
+    }
+
+    /// The transitions out of `state`, one per target
+    pub(crate) fn transitions(&self, state: usize) -> Vec<(CharSet, usize)> {
+        let mut targets: Vec<(Vec<(char, char)>, usize)> = Vec::new();
+        for &(begin, end, target) in &self.states[state].edges {
+            match targets.iter_mut().find(|&&mut (_, t)| t == target) {
+                Some(&mut (ref mut ranges, _)) => ranges.push((begin, end)),
+                None => targets.push((vec![(begin, end)], target)),
+            }
+        }
+        targets.into_iter().map(|(ranges, target)| (CharSet::from_ranges(ranges), target)).collect()
+    }
+
+    /// Which states an accepting state can be reached from
+    pub(crate) fn live(&self) -> Vec<bool> {
+        let mut live: Vec<bool> = self.states.iter().map(|state| state.accepting).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (state, dstate) in self.states.iter().enumerate() {
+                if !live[state] && dstate.edges.iter().any(|&(_, _, target)| live[target]) {
+                    live[state] = true;
+                    changed = true;
+                }
+            }
+        }
+        live
+    }
+
+    /// The state reached by reading `text` from `state`
+    pub(crate) fn run(&self, state: usize, text: &str) -> usize {
+        text.chars().fold(state, |state, c| self.target(state, c))
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.states[self.run(0, text)].accepting
+    }
+
+    /// Accepts the texts both `self` and `other` accept, giving up past
+    /// `STATE_LIMIT` states
+    pub fn intersection(&self, other: &Dfa) -> Result<Dfa, TooLarge> {
+        self.intersection_limited(other, STATE_LIMIT)
+    }
+
+    /// Like `intersection`, giving up past `limit` states
+    pub fn intersection_limited(&self, other: &Dfa, limit: usize) -> Result<Dfa, TooLarge> {
+        self.product(other, limit, |a, b| a && b)
+    }
+
+    /// Accepts the texts either `self` or `other` accepts, giving up past
+    /// `STATE_LIMIT` states
+    pub fn union(&self, other: &Dfa) -> Result<Dfa, TooLarge> {
+        self.union_limited(other, STATE_LIMIT)
+    }
+
+    /// Like `union`, giving up past `limit` states
+    pub fn union_limited(&self, other: &Dfa, limit: usize) -> Result<Dfa, TooLarge> {
+        self.product(other, limit, |a, b| a || b)
+    }
+
+    /// Accepts the texts `self` accepts and `other` doesn't, giving up past
+    /// `STATE_LIMIT` states
+    pub fn difference(&self, other: &Dfa) -> Result<Dfa, TooLarge> {
+        self.difference_limited(other, STATE_LIMIT)
+    }
+
+    /// Like `difference`, giving up past `limit` states
+    pub fn difference_limited(&self, other: &Dfa, limit: usize) -> Result<Dfa, TooLarge> {
+        self.product(other, limit, |a, b| a && !b)
+    }
+
+    /// Accepts the texts `self` doesn't. It has as many states as `self`, so
+    /// it can't be too large.
+    pub fn complement(&self) -> Dfa {
+        let states = self.states.iter().map(|state| DState {
+            accepting: !state.accepting,
+            edges: state.edges.clone(),
+        });
+        Dfa { states: states.collect() }
+    }
+
+    /// Runs `self` and `other` side by side. A text is accepted if `keep` is
+    /// true of whether each of them accepts it. Gives up past `limit` states.
+    fn product<F: Fn(bool, bool) -> bool>(&self, other: &Dfa, limit: usize, keep: F)
+        -> Result<Dfa, TooLarge> {
+        let mut ids = HashMap::new();
+        let mut pairs = vec![(0, 0)];
+        ids.insert((0, 0), 0);
+        let mut states = Vec::new();
+
+        while states.len() < pairs.len() {
+            if pairs.len() > limit {
+                return Err(TooLarge { limit });
+            }
+            let (a, b) = pairs[states.len()];
+            let (a, b) = (&self.states[a], &other.states[b]);
+            let mut edges = Vec::new();
+            let (mut i, mut j, mut begin) = (0, 0, '\0');
+
+            loop {
+                let (a_edge, b_edge) = (a.edges[i], b.edges[j]);
+                let end = ::std::cmp::min(a_edge.1, b_edge.1);
+                let pair = (a_edge.2, b_edge.2);
+                let target = *ids.entry(pair).or_insert_with(|| {
+                    pairs.push(pair);
+                    pairs.len() - 1
+                });
+                push_edge(&mut edges, begin, end, target);
+
+                match next_char(end) {
+                    Some(next) => begin = next,
+                    None => break,
+                }
+                if a_edge.1 == end {
+                    i += 1;
+                }
+                if b_edge.1 == end {
+                    j += 1;
+                }
+            }
+
+            states.push(DState { accepting: keep(a.accepting, b.accepting), edges });
+        }
+
+        Ok(Dfa { states })
+    }
+
+    /// One of the shortest accepted texts
+    fn shortest(&self) -> Option<String> {
+        self.shortest_from(0, true)
+    }
+
+    /// One of the shortest texts leading from `from` to a state that is
+    /// accepting or not, as `accepting` says
+    pub(crate) fn shortest_from(&self, from: usize, accepting: bool) -> Option<String> {
+        let mut parents: Vec<Option<(usize, char)>> = vec![None; self.states.len()];
+        let mut seen = vec![false; self.states.len()];
+        let mut queue = VecDeque::new();
+        seen[from] = true;
+        queue.push_back(from);
+
+        while let Some(state) = queue.pop_front() {
+            if self.states[state].accepting == accepting {
+                let mut text = Vec::new();
+                let mut at = state;
+                while let Some((parent, c)) = parents[at] {
+                    text.push(c);
+                    at = parent;
+                }
+                return Some(text.into_iter().rev().collect());
+            }
+            // The most readable character leading to each new state
+            let mut steps: Vec<(usize, (usize, char))> = Vec::new();
+            for &(begin, end, target) in &self.states[state].edges {
+                if seen[target] {
+                    continue;
+                }
+                let picked = pick(begin, end);
+                match steps.iter_mut().find(|step| step.0 == target) {
+                    Some(step) => step.1 = ::std::cmp::min(step.1, picked),
+                    None => steps.push((target, picked)),
+                }
+            }
+            for (target, (_, c)) in steps {
+                seen[target] = true;
+                parents[target] = Some((state, c));
+                queue.push_back(target);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use dfa::{equivalent, is_subset, Counterexample, Dfa, TooLarge};
+    use graph::{Graph, Options};
+
+    fn graph(pat: &str) -> Graph {
+        Graph::from_string(pat).unwrap()
+    }
+
+    fn counterexample(text: &str, accepted_by_a: bool) -> Option<Counterexample> {
+        Some(Counterexample { text: text.to_string(), accepted_by_a })
+    }
+
+    #[test]
+    fn search_and_anchored() {
+        let search = Dfa::new(&graph("ab|c")).unwrap();
+        assert!(search.is_match("xaby"));
+        assert!(search.is_match("c"));
+        assert!(!search.is_match("axb"));
+
+        let anchored = Dfa::anchored(&graph("ab|c")).unwrap();
+        assert!(anchored.is_match("ab"));
+        assert!(!anchored.is_match("xab"));
+        assert!(!anchored.is_match("abc"));
+    }
+
+    #[test]
+    fn assertions() {
+        let mut options = Options::new();
+        options.multiline(true).crlf(true);
+        let lines = Graph::from_string_with("^b$", &options).unwrap();
+        let dfa = Dfa::new(&lines).unwrap();
+        assert!(dfa.is_match("a\r\nb\r\nc"));
+        assert!(!dfa.is_match("a\r\nbb"));
+        assert!(!Dfa::new(&graph("a^b")).unwrap().is_match("ab"));
+    }
+
+    #[test]
+    fn equivalence() {
+        assert_eq!(None, equivalent(&graph("^a+$"), &graph("^aa*$")).unwrap());
+        assert_eq!(None, equivalent(&graph("ab"), &graph("ab.*|ab")).unwrap());
+        assert_eq!(counterexample("", false), equivalent(&graph("^a+$"), &graph("^a*$")).unwrap());
+        assert_eq!(counterexample("b", true), equivalent(&graph("^(a|b)$"), &graph("^a$")).unwrap());
+        assert_eq!(counterexample("", true), equivalent(&graph("^$"), &graph("a")).unwrap());
+    }
+
+    #[test]
+    fn inclusion() {
+        assert_eq!(None, is_subset(&graph("^ab$"), &graph("^a.*$")).unwrap());
+        assert_eq!(None, is_subset(&graph("^$"), &graph("")).unwrap());
+        assert_eq!(counterexample("a", true), is_subset(&graph("^a.*$"), &graph("^ab$")).unwrap());
+        let err = is_subset(&graph("^[^a]$"), &graph("^[b-z]$")).unwrap().unwrap();
+        assert_eq!(r#""A" is accepted by the first pattern but not the second"#, err.to_string());
+    }
+
+    #[test]
+    fn set_operations() {
+        let letters = Dfa::new(&graph("^[a-z]+$")).unwrap();
+        let keyword = Dfa::new(&graph("^(if|in)$")).unwrap();
+
+        let name = letters.difference(&keyword).unwrap();
+        assert!(name.is_match("it"));
+        assert!(!name.is_match("in"));
+        assert!(!name.is_match("i1"));
+
+        let either = keyword.union(&Dfa::new(&graph("^[0-9]$")).unwrap()).unwrap();
+        assert!(either.is_match("if"));
+        assert!(either.is_match("7"));
+        assert!(!either.is_match("if7"));
+
+        let both = letters.intersection(&Dfa::new(&graph("x")).unwrap()).unwrap();
+        assert!(both.is_match("axb"));
+        assert!(!both.is_match("ab"));
+        assert!(!both.is_match("x1"));
+
+        let no_a = Dfa::new(&graph("a")).unwrap().complement();
+        assert!(no_a.is_match(""));
+        assert!(no_a.is_match("bcd"));
+        assert!(!no_a.is_match("bad"));
+    }
+
+    #[test]
+    fn state_limit() {
+        // The last 20 characters have to be remembered
+        let pat = format!("(a|b)*a{}", "(a|b)".repeat(20));
+        let blowup = graph(&pat);
+        assert_eq!(Err(TooLarge { limit: 1000 }), Dfa::new_limited(&blowup, 1000));
+        assert_eq!(Err(TooLarge { limit: 1000 }), Dfa::anchored_limited(&blowup, 1000));
+        assert!(Dfa::new_limited(&graph("(a|b)*a(a|b)"), 8).is_ok());
+        assert!(equivalent(&blowup, &graph("a")).is_err());
+
+        let few = Dfa::new(&graph("^(a|b)*a(a|b)(a|b)$")).unwrap();
+        let many = few.union_limited(&few.complement(), 4);
+        assert_eq!(Err(TooLarge { limit: 4 }), many);
+        assert!(few.intersection_limited(&few, few.state_count()).is_ok());
+    }
+
+    #[test]
+    fn operators() {
+        let no_if = Dfa::from_string("^~(.*if.*)$").unwrap();
+        assert!(no_if.is_match("elsewhere"));
+        assert!(!no_if.is_match("elif"));
+
+        let both = Dfa::from_string("^(a.*&.*b)c$").unwrap();
+        assert!(both.is_match("abc"));
+        assert!(both.is_match("axxbc"));
+        assert!(!both.is_match("bac"));
+
+        // Complements nest, and repeat like any other group
+        let twice = Dfa::from_string("^~(~(ab))*$").unwrap();
+        assert!(twice.is_match("abab"));
+        assert!(!twice.is_match("aba"));
+        assert!(Dfa::from_string("^(~(a*)&b)$").unwrap().is_match("b"));
+        assert!(!Dfa::from_string("^(a&b)$").unwrap().is_match("a"));
+    }
+
+    #[test]
+    fn operators_are_rejected() {
+        let mut config = ::stream::ParserConfig::new();
+        config.operators(true);
+        let parser = ::stream::TokenParser::from_string_with("~(a)", &config).unwrap();
+        assert_eq!(Err("~(...) and & can only be matched by dfa::Dfa".to_string()),
+            Graph::from_stream(parser));
+        assert!(Dfa::from_string("~(^a)").is_err());
+        assert!(Dfa::from_string("^a&b$").is_err());
+        assert!(Dfa::from_string("~a").is_err());
+        // Literal characters without the config
+        assert!(graph("^~(a)&$").is_match("~a&"));
+    }
+
+    proptest! {
+        #[test]
+        fn operators_agree_with_set_operations(a in r"[ab|()*?]{0,6}", b in r"[ab|()*?]{0,6}", texts in prop::collection::vec("[ab]{0,5}", 8)) {
+            let anchored = |pat: &str| Graph::from_string(pat).and(Graph::from_string(&format!("^({})$", pat)));
+            if let (Ok(ga), Ok(gb)) = (anchored(&a), anchored(&b)) {
+                let (da, db) = (Dfa::new(&ga).unwrap(), Dfa::new(&gb).unwrap());
+                let and = Dfa::from_string(&format!("^(({})&({}))$", a, b)).unwrap();
+                let not = Dfa::from_string(&format!("^~({})$", a)).unwrap();
+                for text in &texts {
+                    prop_assert_eq!(da.is_match(text) && db.is_match(text), and.is_match(text));
+                    prop_assert_eq!(!da.is_match(text), not.is_match(text));
+                }
+            }
+        }
+
+        #[test]
+        fn combined_matchers(a in r"[ab^$|()*?]{0,7}", b in r"[ab^$|()*?]{0,7}", texts in prop::collection::vec("[ab]{0,5}", 8)) {
+            if let (Ok(a), Ok(b)) = (Graph::from_string(&a), Graph::from_string(&b)) {
+                let (dfa_a, dfa_b) = (Dfa::new(&a).unwrap(), Dfa::new(&b).unwrap());
+                let (and, or) = (dfa_a.intersection(&dfa_b).unwrap(), dfa_a.union(&dfa_b).unwrap());
+                let (minus, not) = (dfa_a.difference(&dfa_b).unwrap(), dfa_a.complement());
+                for text in &texts {
+                    let (in_a, in_b) = (a.is_match(text), b.is_match(text));
+                    prop_assert_eq!(in_a && in_b, and.is_match(text));
+                    prop_assert_eq!(in_a || in_b, or.is_match(text));
+                    prop_assert_eq!(in_a && !in_b, minus.is_match(text));
+                    prop_assert_eq!(!in_a, not.is_match(text));
+                }
+            }
+        }
+
+        #[test]
+        fn agrees_with_graph(pat in r"[ab^$|()*+?.\[\]]{0,10}", texts in prop::collection::vec("[ab\n]{0,5}", 8)) {
+            if let Ok(graph) = Graph::from_string(&pat) {
+                let dfa = Dfa::new(&graph).unwrap();
+                for text in &texts {
+                    prop_assert_eq!(graph.is_match(text), dfa.is_match(text), "{:?} on {:?}", pat, text);
+                }
+            }
+        }
+
+        #[test]
+        fn counterexamples_tell_apart(a in r"[ab^$|()*?]{0,7}", b in r"[ab^$|()*?]{0,7}") {
+            if let (Ok(a), Ok(b)) = (Graph::from_string(&a), Graph::from_string(&b)) {
+                if let Some(counter) = equivalent(&a, &b).unwrap() {
+                    prop_assert_eq!(counter.accepted_by_a, a.is_match(&counter.text));
+                    prop_assert_eq!(!counter.accepted_by_a, b.is_match(&counter.text));
+                }
+            }
+        }
+    }
+}
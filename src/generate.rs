@@ -0,0 +1,504 @@
+//! Strings that patterns match, for documentation and tests.
+//!
+//! Every generated string is matched by the pattern as a whole, like
+//! `Dfa::anchored` accepts them, under the graph's `Options`. Each method
+//! builds that automaton first, and fails with `TooLarge` if it needs more
+//! than `dfa::STATE_LIMIT` states.
+//!
+//! ```
+//! use toy_regex::graph::Graph;
+//!
+//! let graph = Graph::from_string(r"^v\d(\.\d)?$").unwrap();
+//! assert_eq!(Some("v0".to_string()), graph.shortest_match().unwrap());
+//! assert_eq!(vec!["v0", "v1", "v2"], graph.examples(3).unwrap());
+//! ```
+//!
+//! With the `rand` feature, `Graph::sample` draws random strings of a given
+//! length, all equally likely, and `Graph::random_walk` cheaper ones of any
+//! length up to a limit.
+//!
+//! The strings of each length can also be counted. Every character is a
+//! different string, so `.` alone matches over a million.
+//!
+//! ```
+//! use toy_regex::generate::{BigUint, Growth};
+//! use toy_regex::graph::Graph;
+//!
+//! let pin = Graph::from_string(r"^\d\d\d\d$").unwrap();
+//! assert_eq!(BigUint::from(10_000u32), pin.count(4).unwrap());
+//! assert_eq!(Growth::Finite, pin.growth().unwrap());
+//!
+//! let name = Graph::from_string("^[a-z][a-z0-9]*$").unwrap();
+//! assert_eq!(26 * 36 * 36, name.count_saturating(3).unwrap());
+//! assert_eq!(Growth::Exponential, name.growth().unwrap());
+//! ```
+
+use std::char;
+
+pub use num_bigint::BigUint;
+#[cfg(feature = "rand")]
+use rand::Rng;
+
+use charset::next_char;
+use dfa::{Dfa, TooLarge};
+use graph::Graph;
+use scc;
+
+impl Graph {
+    /// One of the shortest strings the pattern matches, picking letters and
+    /// digits over other characters where it can. `None` if it matches
+    /// nothing.
+    pub fn shortest_match(&self) -> Result<Option<String>, TooLarge> {
+        Ok(Dfa::anchored(self)?.shortest_from(0, true))
+    }
+
+    /// The first `n` strings the pattern matches, shortest first and in
+    /// order of code points among those of the same length. Fewer if the
+    /// pattern doesn't match that many.
+    pub fn examples(&self, n: usize) -> Result<Vec<String>, TooLarge> {
+        let dfa = Dfa::anchored(self)?;
+        let states = dfa.state_count();
+        let mut examples = Examples {
+            dfa: &dfa,
+            found: Vec::new(),
+            wanted: n,
+            ends: vec![(0..states).map(|state| dfa.is_accepting(state)).collect()],
+        };
+
+        // If finitely many, no string is longer than the number of states
+        let infinite = growth(&dfa) != Growth::Finite;
+        let mut len = 0;
+        while examples.found.len() < n && (infinite || len < states) {
+            examples.extend_ends(len);
+            if examples.ends[len][0] {
+                examples.collect(0, len, &mut String::new());
+            }
+            len += 1;
+        }
+        Ok(examples.found)
+    }
+}
+
+/// How the number of strings of each length a pattern matches grows with
+/// the length
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Growth {
+    /// No string is longer than some length
+    Finite,
+    /// At most some multiple of `len` to the power of the degree for each
+    /// `len`. Degree 0 is a bounded number of strings per length, as `a*`
+    /// has.
+    Polynomial(u32),
+    Exponential,
+}
+
+impl Graph {
+    /// The number of strings of `len` characters the pattern matches
+    pub fn count(&self, len: usize) -> Result<BigUint, TooLarge> {
+        let dfa = Dfa::anchored(self)?;
+        let mut counts = ends(&dfa);
+        for _ in 0..len {
+            counts = longer(&dfa, &counts);
+        }
+        Ok(counts.swap_remove(0))
+    }
+
+    /// Like `count`, with `u64::MAX` standing for any number from there up
+    pub fn count_saturating(&self, len: usize) -> Result<u64, TooLarge> {
+        let dfa = Dfa::anchored(self)?;
+        let mut counts: Vec<u64> = (0..dfa.state_count())
+            .map(|state| dfa.is_accepting(state) as u64)
+            .collect();
+        for _ in 0..len {
+            counts = (0..dfa.state_count())
+                .map(|state| {
+                    dfa.transitions(state).iter().fold(0u64, |count, &(ref set, target)| {
+                        count.saturating_add(counts[target].saturating_mul(set.len() as u64))
+                    })
+                })
+                .collect();
+        }
+        Ok(counts[0])
+    }
+
+    /// How the number of strings of each length the pattern matches grows
+    pub fn growth(&self) -> Result<Growth, TooLarge> {
+        Ok(growth(&Dfa::anchored(self)?))
+    }
+}
+
+/// Strings grow exponentially when a cycle can be left and come back to
+/// on two characters. Otherwise each cycle on the way to acceptance adds a
+/// degree, but the first only makes them unbounded.
+fn growth(dfa: &Dfa) -> Growth {
+    let live = dfa.live();
+    let next: Vec<Vec<usize>> = (0..dfa.state_count())
+        .map(|state| {
+            let mut targets: Vec<usize> = dfa.edges(state).iter()
+                .map(|&(_, _, target)| target)
+                .filter(|&target| live[state] && live[target])
+                .collect();
+            targets.sort();
+            targets.dedup();
+            targets
+        })
+        .collect();
+
+    let components = scc::components(&next);
+    let mut component_of = vec![0; dfa.state_count()];
+    for (i, component) in components.iter().enumerate() {
+        for &state in component {
+            component_of[state] = i;
+        }
+    }
+
+    // The most cycles on a path from each component, sinks first
+    let mut cycles: Vec<u32> = Vec::with_capacity(components.len());
+    for (i, component) in components.iter().enumerate() {
+        let mut staying = 0;
+        let mut after = 0;
+        for &state in component {
+            let inside: u32 = dfa.transitions(state).iter()
+                .filter(|&&(_, target)| live[state] && live[target] && component_of[target] == i)
+                .map(|(set, _)| set.len())
+                .sum();
+            if inside > 1 {
+                return Growth::Exponential;
+            }
+            staying += inside;
+            for &target in &next[state] {
+                if component_of[target] != i {
+                    after = after.max(cycles[component_of[target]]);
+                }
+            }
+        }
+        cycles.push(after + (staying > 0) as u32);
+    }
+
+    match cycles[component_of[0]] {
+        0 => Growth::Finite,
+        cycles => Growth::Polynomial(cycles - 1),
+    }
+}
+
+/// Whether each state accepts, as a number of strings
+fn ends(dfa: &Dfa) -> Vec<BigUint> {
+    (0..dfa.state_count()).map(|state| BigUint::from(dfa.is_accepting(state) as u32)).collect()
+}
+
+/// The number of strings from each state to acceptance that are one
+/// character longer than `shorter` counts
+fn longer(dfa: &Dfa, shorter: &[BigUint]) -> Vec<BigUint> {
+    (0..dfa.state_count())
+        .map(|state| {
+            dfa.transitions(state).iter()
+                .map(|&(ref set, target)| &shorter[target] * set.len())
+                .sum()
+        })
+        .collect()
+}
+
+/// `counts[len][state]`: the number of strings of `len` characters leading
+/// from `state` to acceptance, for every `len` up to `max`
+#[cfg(feature = "rand")]
+fn counts(dfa: &Dfa, max: usize) -> Vec<Vec<BigUint>> {
+    let mut counts = vec![ends(dfa)];
+    for _ in 0..max {
+        let count = longer(dfa, counts.last().unwrap());
+        counts.push(count);
+    }
+    counts
+}
+
+#[cfg(feature = "rand")]
+impl Graph {
+    /// A random string of `len` characters that the pattern matches, each
+    /// of them as likely. `None` if there are none.
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R, len: usize)
+        -> Result<Option<String>, TooLarge> {
+        let dfa = Dfa::anchored(self)?;
+        let counts = counts(&dfa, len);
+        if counts[len][0] == BigUint::from(0u32) {
+            return Ok(None);
+        }
+
+        // The `pick`th string in the order of the transitions taken
+        let mut pick = below(rng, &counts[len][0]);
+        let mut text = String::with_capacity(len);
+        let mut state = 0;
+        for left in (0..len).rev() {
+            for (set, target) in dfa.transitions(state) {
+                let each = &counts[left][target];
+                let strings = each * set.len();
+                if pick < strings {
+                    let index = &pick / each;
+                    text.push(set.nth(index.iter_u32_digits().next().unwrap_or(0)).unwrap());
+                    pick %= each;
+                    state = target;
+                    break;
+                }
+                pick -= strings;
+            }
+        }
+        Ok(Some(text))
+    }
+
+    /// A random string of at most `max_len` characters that the pattern
+    /// matches, found by stopping or taking a transition with equal odds at
+    /// each step. Much cheaper than `sample`, but some strings are far
+    /// likelier than others. `None` if there are none.
+    pub fn random_walk<R: Rng + ?Sized>(&self, rng: &mut R, max_len: usize)
+        -> Result<Option<String>, TooLarge> {
+        let dfa = Dfa::anchored(self)?;
+        // `within[len][state]`: whether `state` can reach acceptance in at
+        // most `len` characters
+        let mut within: Vec<Vec<bool>> =
+            vec![(0..dfa.state_count()).map(|state| dfa.is_accepting(state)).collect()];
+        for _ in 0..max_len {
+            let shorter = within.last().unwrap();
+            let next = (0..dfa.state_count())
+                .map(|state| {
+                    shorter[state]
+                        || dfa.edges(state).iter().any(|&(_, _, target)| shorter[target])
+                })
+                .collect();
+            within.push(next);
+        }
+        if !within[max_len][0] {
+            return Ok(None);
+        }
+
+        let mut text = String::new();
+        let mut state = 0;
+        for left in (0..max_len).rev() {
+            let moves: Vec<_> = dfa.transitions(state).into_iter()
+                .filter(|&(_, target)| within[left][target])
+                .collect();
+            let stop = dfa.is_accepting(state) as usize;
+            let choice = rng.gen_range(0..stop + moves.len());
+            if choice < stop {
+                return Ok(Some(text));
+            }
+            let (ref set, target) = moves[choice - stop];
+            text.push(set.nth(rng.gen_range(0..set.len())).unwrap());
+            state = target;
+        }
+        Ok(Some(text))
+    }
+}
+
+/// A uniformly random number below `bound`, which isn't 0
+#[cfg(feature = "rand")]
+fn below<R: Rng + ?Sized>(rng: &mut R, bound: &BigUint) -> BigUint {
+    let bits = bound.bits();
+    let mut bytes = vec![0; bits.div_ceil(8) as usize];
+    loop {
+        rng.fill_bytes(&mut bytes);
+        // Clearing the bits above the bound's highest one, so that at least
+        // half the draws are kept
+        let extra = bytes.len() as u64 * 8 - bits;
+        *bytes.last_mut().unwrap() &= 0xFF >> extra;
+        let number = BigUint::from_bytes_le(&bytes);
+        if number < *bound {
+            return number;
+        }
+    }
+}
+
+struct Examples<'a> {
+    dfa: &'a Dfa,
+    found: Vec<String>,
+    wanted: usize,
+    /// `ends[len][state]`: whether some string of `len` characters leads
+    /// from `state` to acceptance
+    ends: Vec<Vec<bool>>,
+}
+
+impl<'a> Examples<'a> {
+    fn extend_ends(&mut self, len: usize) {
+        while self.ends.len() <= len {
+            let shorter = self.ends.last().unwrap();
+            let ends = (0..self.dfa.state_count())
+                .map(|state| self.dfa.edges(state).iter().any(|&(_, _, target)| shorter[target]))
+                .collect();
+            self.ends.push(ends);
+        }
+    }
+
+    /// Adds the accepted strings of `len` more characters after `text`,
+    /// which leads to `state`, in order
+    fn collect(&mut self, state: usize, len: usize, text: &mut String) {
+        if len == 0 {
+            self.found.push(text.clone());
+            return;
+        }
+        for &(begin, end, target) in self.dfa.edges(state) {
+            if !self.ends[len - 1][target] {
+                continue;
+            }
+            let mut c = Some(begin);
+            while let Some(next) = c.filter(|&c| c <= end) {
+                if self.found.len() == self.wanted {
+                    return;
+                }
+                text.push(next);
+                self.collect(target, len - 1, text);
+                text.pop();
+                c = next_char(next);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use generate::{BigUint, Growth};
+    use graph::{Graph, Options};
+
+    fn examples(pat: &str, n: usize) -> Vec<String> {
+        Graph::from_string(pat).unwrap().examples(n).unwrap()
+    }
+
+    #[test]
+    fn shortest() {
+        let shortest = |pat: &str| Graph::from_string(pat).unwrap().shortest_match().unwrap();
+        assert_eq!(Some("".to_string()), shortest("a*"));
+        assert_eq!(Some("ab".to_string()), shortest("^(abc|ab|b+c)$"));
+        assert_eq!(Some("a".to_string()), shortest(r"^[^\s]$"));
+        assert_eq!(Some("xa".to_string()), shortest(r"^x[[:alpha:]]+$"));
+        assert_eq!(None, shortest("a^b"));
+    }
+
+    #[test]
+    fn length_lexicographic() {
+        assert_eq!(vec!["", "a", "b", "aa", "ab", "ba"], examples("^[ab]*$", 6));
+        assert_eq!(vec!["ab", "abc"], examples("^(abc|ab)$", 5));
+        assert_eq!(vec!["", "a"], examples("a?", 5));
+        assert_eq!(Vec::<String>::new(), examples(r"^$x", 5));
+        assert_eq!(Vec::<String>::new(), examples("a*", 0));
+        assert_eq!(vec!["\0", "\u{1}"], examples(".", 2));
+    }
+
+    #[test]
+    fn options_and_classes() {
+        let mut options = Options::new();
+        options.case_insensitive(true);
+        let graph = Graph::from_string_with("^x[[:digit:]]$", &options).unwrap();
+        assert_eq!(vec!["X0", "X1", "X2"], graph.examples(3).unwrap());
+
+        options = Options::new();
+        options.multiline(true);
+        let graph = Graph::from_string_with("^a$\n^b$", &options).unwrap();
+        assert_eq!(vec!["a\nb"], graph.examples(2).unwrap());
+    }
+
+    #[test]
+    fn counts() {
+        let count = |pat: &str, len: usize| Graph::from_string(pat).unwrap().count(len).unwrap();
+        assert_eq!(BigUint::from(8u32), count("^[ab]*$", 3));
+        assert_eq!(BigUint::from(1u32), count("^[ab]*$", 0));
+        assert_eq!(BigUint::from(0u32), count("^(abc|ab)$", 4));
+        assert_eq!(BigUint::from(4u32), count("^a*b*c*$", 2) - BigUint::from(2u32));
+        assert_eq!(BigUint::from(1_112_063u32), count("^.$", 1));
+        assert_eq!(BigUint::from(1_112_063u32).pow(5), count("^.*$", 5));
+    }
+
+    #[test]
+    fn saturating_counts() {
+        let graph = Graph::from_string("^.*$").unwrap();
+        assert_eq!(1_112_063 * 1_112_063, graph.count_saturating(2).unwrap());
+        assert_eq!(u64::MAX, graph.count_saturating(4).unwrap());
+        assert_eq!(0, Graph::from_string("a^b").unwrap().count_saturating(2).unwrap());
+    }
+
+    #[test]
+    fn growth() {
+        let growth = |pat: &str| Graph::from_string(pat).unwrap().growth().unwrap();
+        assert_eq!(Growth::Finite, growth("^(abc|ab)$"));
+        assert_eq!(Growth::Finite, growth("a^b"));
+        assert_eq!(Growth::Finite, growth("^a?$"));
+        assert_eq!(Growth::Polynomial(0), growth("^a*$"));
+        assert_eq!(Growth::Polynomial(0), growth("^(ab)*[xy]$"));
+        assert_eq!(Growth::Polynomial(1), growth("^a*b*$"));
+        assert_eq!(Growth::Polynomial(2), growth("^a*-b*-c*$"));
+        assert_eq!(Growth::Exponential, growth("^(ab|ba)*$"));
+        assert_eq!(Growth::Exponential, growth("^x[ab]*$"));
+    }
+
+    #[cfg(feature = "rand")]
+    mod sampling {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        use graph::Graph;
+
+        #[test]
+        fn uniform() {
+            let graph = Graph::from_string("^(a|b[0-9])[xy]?$").unwrap();
+            let mut rng = StdRng::seed_from_u64(7);
+            let mut seen = ::std::collections::HashMap::new();
+            for _ in 0..2000 {
+                let text = graph.sample(&mut rng, 2).unwrap().unwrap();
+                assert!(graph.is_match(&text), "{:?}", text);
+                *seen.entry(text).or_insert(0) += 1;
+            }
+            // "ax", "ay" and "b0" to "b9", about 167 times each
+            assert_eq!(12, seen.len());
+            assert!(seen.values().all(|&n| n > 110 && n < 230), "{:?}", seen);
+            assert_eq!(None, graph.sample(&mut rng, 4).unwrap());
+            assert_eq!(Some("a".to_string()), graph.sample(&mut rng, 1).unwrap());
+        }
+
+        #[test]
+        fn walks() {
+            let graph = Graph::from_string(r"^x(ab|\d)*y$").unwrap();
+            let mut rng = StdRng::seed_from_u64(7);
+            for _ in 0..200 {
+                let text = graph.random_walk(&mut rng, 6).unwrap().unwrap();
+                assert!(graph.is_match(&text) && text.len() <= 6, "{:?}", text);
+            }
+            assert_eq!(None, graph.random_walk(&mut rng, 1).unwrap());
+            assert_eq!(Some("xy".to_string()), graph.random_walk(&mut rng, 2).unwrap());
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn examples_match_in_order(pat in r"[ab|()*+?^$]{0,10}") {
+            if let Ok(graph) = Graph::from_string(&pat) {
+                let found = graph.examples(10).unwrap();
+                let anchored = ::dfa::Dfa::anchored(&graph).unwrap();
+                for example in &found {
+                    prop_assert!(anchored.is_match(example), "{:?} for {}", example, pat);
+                }
+                for pair in found.windows(2) {
+                    let key = |text: &String| (text.chars().count(), text.clone());
+                    prop_assert!(key(&pair[0]) < key(&pair[1]), "{:?} for {}", pair, pat);
+                }
+                let shortest = graph.shortest_match().unwrap();
+                prop_assert_eq!(found.first().map(|text| text.chars().count()),
+                    shortest.map(|text| text.chars().count()));
+            }
+        }
+
+        #[test]
+        fn counts_agree_with_matching(pat in r"[ab|()*+?^$]{0,10}", len in 0usize..6) {
+            if let Ok(graph) = Graph::from_string(&pat) {
+                let anchored = ::dfa::Dfa::anchored(&graph).unwrap();
+                let texts = (0..len).fold(vec![String::new()], |texts, _| {
+                    texts.iter().flat_map(|text| vec![format!("{}a", text), format!("{}b", text)])
+                        .collect()
+                });
+                let matched = texts.iter().filter(|text| anchored.is_match(text)).count() as u64;
+                prop_assert_eq!(BigUint::from(matched), graph.count(len).unwrap(), "{}", pat);
+                prop_assert_eq!(matched, graph.count_saturating(len).unwrap(), "{}", pat);
+                if graph.growth().unwrap() == Growth::Finite {
+                    let states = anchored.state_count();
+                    prop_assert_eq!(BigUint::from(0u32), graph.count(states).unwrap(), "{}", pat);
+                }
+            }
+        }
+    }
+}
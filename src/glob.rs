@@ -0,0 +1,246 @@
+//! Shell-style globs over `/` separated paths, with the `**` rules of
+//! gitignore and bash's `globstar`.
+//!
+//! * `*` matches any run of characters other than `/`, and `?` any single
+//!   one.
+//! * `**` as a whole path segment crosses separators: `**/` matches zero or
+//!   more directories and a trailing `/**` everything below. Anywhere else it
+//!   acts like `*`.
+//! * `[...]` is a bracket expression, negated by `!` or `^`. It never matches
+//!   `/`, negated or not.
+//! * `{a,b}` matches either alternative, and alternatives can nest.
+//! * `\` makes the next character literal.
+//!
+//! A glob has to match the whole path, so the tokens are anchored at both
+//! ends of the text, even when compiled with `graph::Options::multiline`.
+
+use stream::{wrap_span, Cursor, ParserConfig, Span, Spanned};
+
+struct Parser<'a> {
+    glob: &'a str,
+    chars: Cursor<'a>,
+}
+
+/// `[^/]`, for the syntax at `span`
+fn not_separator(span: Span) -> Spanned {
+    token_use!();
+    wrap_span(GBegin::Not, GEnd::Not, span.clone(), vec![(Literal(Char('/')), span)])
+}
+
+impl<'a> Parser<'a> {
+    /// Reads a bracket expression whose `[` was just consumed, without the
+    /// `/` it might otherwise match: `[...]` is made `[^/[^...]]`.
+    fn bracket(&mut self) -> Result<Spanned, String> {
+        token_use!();
+        let toks = self.chars.bracket_with(&ParserConfig::default(), &['!', '^'], None)?;
+        let span = toks[0].1.clone();
+
+        let mut toks = match toks[0].0 {
+            Grouping(Begin(GBegin::Not)) => toks,
+            _ => {
+                let outside = wrap_span(GBegin::Not, GEnd::Not, span.clone(), toks);
+                let outside = wrap_span(GBegin::Or, GEnd::Or, span.clone(), outside);
+                wrap_span(GBegin::Not, GEnd::Not, span.clone(), outside)
+            },
+        };
+        let end_or = toks.len() - 2;
+        toks.insert(end_or, (Grouping(OrDelimiter), span.clone()));
+        toks.insert(end_or + 1, (Literal(Char('/')), span));
+        Ok(toks)
+    }
+
+    /// Handles a run of `*`, the first of which was just read
+    fn stars(&mut self, start: usize) -> Spanned {
+        token_use!();
+        let mut count = 1;
+        while self.chars.peek() == Some('*') {
+            self.chars.next();
+            count += 1;
+        }
+
+        let segment_start = self.glob[..start].is_empty() || self.glob[..start].ends_with('/');
+        if count == 2 && segment_start {
+            match self.chars.peek() {
+                Some('/') => {
+                    self.chars.next();
+                    // Zero or more whole directories, `([^/]*/)*`
+                    let span = start..self.chars.pos;
+                    let mut dir = wrap_span(GBegin::Any, GEnd::Any, span.clone(), not_separator(span.clone()));
+                    dir.push((Literal(Char('/')), span.clone()));
+                    let dir = wrap_span(GBegin::Or, GEnd::Or, span.clone(), dir);
+                    return wrap_span(GBegin::Any, GEnd::Any, span, dir);
+                },
+                None => {
+                    let span = start..self.chars.pos;
+                    // Anything at all, whatever the compile options say
+                    // about `AnyChar`
+                    let any = vec![(Literal(Range { begin: '\0', end: ::std::char::MAX }), span.clone())];
+                    return wrap_span(GBegin::Any, GEnd::Any, span, any);
+                },
+                _ => (),
+            }
+        }
+
+        let span = start..self.chars.pos;
+        wrap_span(GBegin::Any, GEnd::Any, span.clone(), not_separator(span))
+    }
+
+    /// Reads up to the end of the glob or, inside braces, up to the next `,`
+    /// or `}`.
+    fn sequence(&mut self, in_brace: bool) -> Result<Spanned, String> {
+        token_use!();
+        let mut toks = Vec::new();
+
+        loop {
+            let start = self.chars.pos;
+            let c = match self.chars.peek() {
+                None if in_brace => return Err("Glob ended inside braces".to_string()),
+                None => return Ok(toks),
+                Some(',') | Some('}') if in_brace => return Ok(toks),
+                Some(_) => self.chars.next().unwrap(),
+            };
+
+            match c {
+                '*' => toks.extend(self.stars(start)),
+                '?' => toks.extend(not_separator(start..self.chars.pos)),
+                '[' => toks.extend(self.bracket()?),
+                '{' => {
+                    let mut alternatives = self.sequence(true)?;
+                    while self.chars.next() == Some(',') {
+                        alternatives.push((Grouping(OrDelimiter), self.chars.pos - 1..self.chars.pos));
+                        alternatives.extend(self.sequence(true)?);
+                    }
+                    toks.extend(wrap_span(GBegin::Or, GEnd::Or, start..self.chars.pos, alternatives));
+                },
+                '\\' => {
+                    let escaped = self.chars.next().ok_or("Glob ended when expecting escaped character".to_string())?;
+                    toks.push((Literal(Char(escaped)), start..self.chars.pos));
+                },
+                _ => toks.push((Literal(Char(c)), start..self.chars.pos)),
+            }
+        }
+    }
+}
+
+/// Parses a glob into a token stream that matches whole paths
+pub fn parse_glob(glob: &str) -> Result<Spanned, String> {
+    token_use!();
+    let mut parser = Parser { glob, chars: Cursor::new(glob) };
+
+    let mut toks = vec![(Literal(StartOfText), 0..0)];
+    toks.extend(parser.sequence(false)?);
+    toks.push((Literal(EndOfText), glob.len()..glob.len()));
+    Ok(wrap_span(GBegin::Pat, GEnd::Pat, 0..glob.len(), toks))
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::Graph;
+    use stream::TokenParser;
+
+    fn matcher(glob: &str) -> Graph {
+        Graph::from_stream(TokenParser::from_glob(glob).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn star_tokens() {
+        use stream;
+        token_use!();
+        let expected_toks = vec![Grouping(Begin(GBegin::Pat)),
+            Literal(StartOfText), Grouping(Begin(GBegin::Any)),
+            Grouping(Begin(GBegin::Not)), Literal(Char('/')),
+            Grouping(End(GEnd::Not)), Grouping(End(GEnd::Any)),
+            Literal(Char('.')), Literal(Char('c')),
+            Literal(EndOfText), Grouping(End(GEnd::Pat))];
+
+        let parser = stream::TokenParser::from_glob("*.c").unwrap();
+        assert_eq!(expected_toks, parser.tokens());
+    }
+
+    #[test]
+    fn star_stays_in_segment() {
+        let glob = matcher("*.txt");
+        assert!(glob.is_match("a.txt"));
+        assert!(glob.is_match(".txt"));
+        assert!(!glob.is_match("dir/a.txt"));
+        assert!(!glob.is_match("a.txt.bak"));
+    }
+
+    #[test]
+    fn whole_text_in_multiline() {
+        use graph::Options;
+        let mut options = Options::new();
+        options.multiline(true);
+        let glob = Graph::from_stream_with(TokenParser::from_glob("src/*.rs").unwrap(), &options).unwrap();
+        assert!(glob.is_match("src/main.rs"));
+        assert!(!glob.is_match("evil\nsrc/main.rs"));
+        assert!(!glob.is_match("src/main.rs\nevil"));
+    }
+
+    #[test]
+    fn globstar() {
+        let glob = matcher("src/**/*.rs");
+        assert!(glob.is_match("src/lib.rs"));
+        assert!(glob.is_match("src/a/b/lib.rs"));
+        assert!(!glob.is_match("src/lib.rs/x"));
+        assert!(!glob.is_match("lib/lib.rs"));
+
+        let leading = matcher("**/target");
+        assert!(leading.is_match("target"));
+        assert!(leading.is_match("a/b/target"));
+        assert!(!leading.is_match("a/btarget"));
+
+        let trailing = matcher("build/**");
+        assert!(trailing.is_match("build/a/b.o"));
+        assert!(trailing.is_match("build/a\nb"));
+        assert!(!trailing.is_match("builds/a"));
+    }
+
+    #[test]
+    fn globstar_doesnt_capture() {
+        use stream;
+        token_use!();
+        let parser = TokenParser::from_glob("**/a/**").unwrap();
+        assert!(!parser.tokens().iter().any(|tok| matches!(*tok, Grouping(Begin(GBegin::Cap { .. })))));
+    }
+
+    #[test]
+    fn double_star_inside_segment() {
+        let glob = matcher("a**b");
+        assert!(glob.is_match("axyb"));
+        assert!(!glob.is_match("ax/yb"));
+    }
+
+    #[test]
+    fn question_and_brackets() {
+        let glob = matcher("?[!x][a-c]");
+        assert!(glob.is_match("zya"));
+        assert!(!glob.is_match("zxa"));
+        assert!(!glob.is_match("z/a"));
+        assert!(!glob.is_match("/ya"));
+        assert!(matcher("[^x]").is_match("y"));
+
+        let range = matcher("a[+-0]b");
+        assert!(range.is_match("a.b"));
+        assert!(!range.is_match("a/b"));
+        assert!(!matcher("[/]").is_match("/"));
+    }
+
+    #[test]
+    fn braces() {
+        let glob = matcher("{a,b{c,d},}.txt");
+        assert!(glob.is_match("a.txt"));
+        assert!(glob.is_match("bd.txt"));
+        assert!(glob.is_match(".txt"));
+        assert!(!glob.is_match("b.txt"));
+        assert!(matcher("a,b}").is_match("a,b}"));
+        assert!(TokenParser::from_glob("{a,b").is_err());
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matcher(r"\*\?").is_match("*?"));
+        assert!(!matcher(r"\*").is_match("a"));
+        assert!(TokenParser::from_glob("a\\").is_err());
+    }
+}
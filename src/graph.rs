@@ -1,20 +1,680 @@
+use std::ops::Range;
+
 use stream;
+use stream::Token;
+
+use ast::{Ast, Repetition};
+use charset::CharSet;
+use dfa::Dfa;
+
+/// Settings that change what a compiled pattern matches.
+///
+/// By default `^` (`StartOfLine`) only matches at the start of the text, `$`
+/// (`EndOfLine`) only at the end, and `.` (`AnyChar`) matches any character
+/// except a line terminator. A line terminator is `\n`, or also `\r` when
+/// `crlf` is set.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Options {
+    case_insensitive: bool,
+    multiline: bool,
+    dot_all: bool,
+    crlf: bool,
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options::default()
+    }
+
+    /// Makes every literal and class match all of its case variants under
+    /// Unicode simple case folding, e.g. `k` also matches `K` and `\u{212A}`.
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut Options {
+        self.case_insensitive = yes;
+        self
+    }
+
+    /// Makes `^` also match right after a line terminator and `$` also match
+    /// right before one.
+    pub fn multiline(&mut self, yes: bool) -> &mut Options {
+        self.multiline = yes;
+        self
+    }
+
+    /// Makes `.` match line terminators too.
+    pub fn dot_all(&mut self, yes: bool) -> &mut Options {
+        self.dot_all = yes;
+        self
+    }
+
+    /// Treats `\r` as a line terminator alongside `\n`, with `\r\n` counting
+    /// as a single one: in multiline mode neither `^` nor `$` match between
+    /// the `\r` and the `\n`.
+    pub fn crlf(&mut self, yes: bool) -> &mut Options {
+        self.crlf = yes;
+        self
+    }
+
+    fn is_terminator(&self, c: char) -> bool {
+        c == '\n' || (self.crlf && c == '\r')
+    }
+
+    /// Whether the assertion holds between `prev` and `next`, the characters
+    /// on either side of a position (`None` at the text edges).
+    pub fn assertion_holds(&self, assertion: Assertion, prev: Option<char>, next: Option<char>)
+        -> bool {
+        match assertion {
+            Assertion::StartOfLine => match prev {
+                None => true,
+                Some(_) if !self.multiline => false,
+                Some('\r') if self.crlf => next != Some('\n'),
+                Some(c) => self.is_terminator(c),
+            },
+            Assertion::EndOfLine => match next {
+                None => true,
+                Some(_) if !self.multiline => false,
+                Some('\n') if self.crlf => prev != Some('\r'),
+                Some(c) => self.is_terminator(c),
+            },
+            Assertion::StartOfText => prev.is_none(),
+            Assertion::EndOfText => next.is_none(),
+        }
+    }
+
+    /// The characters `AnyChar` matches
+    pub fn any_char(&self) -> CharSet {
+        if self.dot_all {
+            CharSet::any()
+        } else if self.crlf {
+            CharSet::from_ranges(vec![('\n', '\n'), ('\r', '\r')]).negate()
+        } else {
+            CharSet::single('\n').negate()
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Assertion {
+    StartOfLine,
+    EndOfLine,
+    StartOfText,
+    EndOfText,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum State {
+    /// Consume one character in the set
+    Char(CharSet, usize),
+    /// Try both branches
+    Split(usize, usize),
+    /// Continue without consuming input if the assertion holds
+    Assert(Assertion, usize),
+    Match,
+}
+
+/// A Thompson NFA compiled from a token stream.
+///
+/// `Complement` and `And` groups are rejected: they can make the automaton
+/// exponentially larger than the pattern, so only `dfa::Dfa` accepts them.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Graph {
+    states: Vec<State>,
+    start: usize,
+    options: Options,
+}
+
+const HOLE: usize = usize::MAX;
+
+/// A partially built piece of the graph. `outs` are the states whose
+/// successor is still `HOLE`.
+struct Frag {
+    start: usize,
+    outs: Vec<usize>,
+}
 
-use typed_arena;
+/// ASCII sets for the POSIX classes
+fn class_set(lit: &stream::Literal) -> Option<CharSet> {
+    use stream::Literal::*;
+    let ranges: &[(char, char)] = match *lit {
+        Whitespace => &[(' ', ' '), ('\t', '\r')],
+        Alnum => &[('0', '9'), ('A', 'Z'), ('a', 'z')],
+        Alpha => &[('A', 'Z'), ('a', 'z')],
+        Blank => &[(' ', ' '), ('\t', '\t')],
+        Cntrl => &[('\0', '\x1F'), ('\x7F', '\x7F')],
+        Digit => &[('0', '9')],
+        Graph => &[('!', '~')],
+        Lower => &[('a', 'z')],
+        Print => &[(' ', '~')],
+        Punct => &[('!', '/'), (':', '@'), ('[', '`'), ('{', '~')],
+        Upper => &[('A', 'Z')],
+        XDigit => &[('0', '9'), ('A', 'F'), ('a', 'f')],
+        Class { ref ranges, .. } => return Some(CharSet::from_ranges(ranges.iter().cloned())),
+        _ => return None,
+    };
+    Some(CharSet::from_ranges(ranges.iter().cloned()))
+}
+
+struct Compiler {
+    states: Vec<State>,
+    options: Options,
+    /// Whether `Complement` and `And` groups are compiled, by building a
+    /// DFA for each and adding its states
+    automata: bool,
+    /// The states each quantifier compiled to, in token order
+    quantifiers: Vec<Range<usize>>,
+}
+
+fn has_assertions(ast: &Ast) -> bool {
+    match *ast {
+        Ast::Assertion(_) => true,
+        Ast::Concat(ref items) | Ast::Alternation(ref items) | Ast::Intersection(ref items) => {
+            items.iter().any(has_assertions)
+        },
+        Ast::Repetition(_, ref inner) | Ast::Group(_, ref inner) | Ast::Not(ref inner)
+            | Ast::Complement(ref inner) => has_assertions(inner),
+        Ast::Literal(_) | Ast::Class(_) => false,
+    }
+}
+
+impl Compiler {
+    fn push(&mut self, state: State) -> usize {
+        self.states.push(state);
+        self.states.len() - 1
+    }
+
+    fn patch(&mut self, outs: &[usize], target: usize) {
+        for &out in outs {
+            match self.states[out] {
+                State::Char(_, ref mut next) | State::Assert(_, ref mut next) => *next = target,
+                State::Split(ref mut a, ref mut b) => {
+                    if *a == HOLE {
+                        *a = target;
+                    } else {
+                        *b = target;
+                    }
+                },
+                State::Match => (),
+            }
+        }
+    }
+
+    /// The set matched by a literal or class, or `None` for assertions
+    fn literal_set(&self, lit: &stream::Literal) -> Option<CharSet> {
+        use stream::Literal::*;
+        let set = match *lit {
+            Char(c) => CharSet::single(c),
+            Range { begin, end } => CharSet::range(begin, end),
+            AnyChar => return Some(self.options.any_char()),
+            StartOfLine | EndOfLine | StartOfText | EndOfText => return None,
+            _ => class_set(lit).expect("Every class literal has a set"),
+        };
+
+        if self.options.case_insensitive {
+            Some(set.case_fold())
+        } else {
+            Some(set)
+        }
+    }
+
+    /// The set of characters a tree inside `Not` describes
+    fn set_of(&self, ast: &Ast) -> Result<CharSet, String> {
+        match *ast {
+            Ast::Literal(c) => Ok(self.literal_set(&stream::Literal::Char(c)).unwrap()),
+            Ast::Class(ref lit) => Ok(self.literal_set(lit).unwrap()),
+            Ast::Concat(ref items) if items.is_empty() => Ok(CharSet::new()),
+            Ast::Alternation(ref branches) => {
+                let mut set = CharSet::new();
+                for branch in branches {
+                    set = set.union(&self.set_of(branch)?);
+                }
+                Ok(set)
+            },
+            Ast::Not(ref inner) => Ok(self.set_of(inner)?.negate()),
+            ref other => Err(format!("{:?} can't be negated", other)),
+        }
+    }
 
-struct Graph {
-    arena: typed_arena::Arena<stream::Token>,
+    /// The DFA of an operand of `Complement` or `And`, matching it as a
+    /// whole
+    fn operand(&self, ast: &Ast) -> Result<Dfa, String> {
+        if has_assertions(ast) {
+            return Err("^ and $ can't be used inside ~(...) or &".to_string());
+        }
+        Ok(Dfa::anchored(&Graph::compile(ast, &self.options, true)?)?)
+    }
+
+    /// Adds the states of `dfa`, leaving out those that can't reach an
+    /// accepting state
+    fn embed(&mut self, dfa: &Dfa) -> Frag {
+        let live = dfa.live();
+        let mut entries = vec![HOLE; live.len()];
+        let mut chars = Vec::new();
+        let mut outs = Vec::new();
+
+        for state in (0..live.len()).filter(|&state| live[state]) {
+            let mut entry = None;
+            if dfa.is_accepting(state) {
+                // Both arms lead out, like the empty sequence
+                let out = self.push(State::Split(HOLE, HOLE));
+                outs.push(out);
+                outs.push(out);
+                entry = Some(out);
+            }
+            for (set, target) in dfa.transitions(state).into_iter().rev() {
+                if !live[target] {
+                    continue;
+                }
+                let arm = self.push(State::Char(set, HOLE));
+                chars.push((arm, target));
+                entry = Some(match entry {
+                    Some(rest) => self.push(State::Split(arm, rest)),
+                    None => arm,
+                });
+            }
+            entries[state] = entry.expect("A live state leads somewhere");
+        }
+
+        for (arm, target) in chars {
+            if let State::Char(_, ref mut next) = self.states[arm] {
+                *next = entries[target];
+            }
+        }
+        if !live[0] {
+            // Nothing matches, so nothing leads out
+            let never = self.push(State::Char(CharSet::new(), HOLE));
+            return Frag { start: never, outs: Vec::new() };
+        }
+        Frag { start: entries[0], outs }
+    }
+
+    fn single(&mut self, state: State) -> Frag {
+        let state = self.push(state);
+        Frag { start: state, outs: vec![state] }
+    }
+
+    fn compile(&mut self, ast: &Ast) -> Result<Frag, String> {
+        let frag = match *ast {
+            Ast::Literal(c) => {
+                let set = self.literal_set(&stream::Literal::Char(c)).unwrap();
+                self.single(State::Char(set, HOLE))
+            },
+            Ast::Class(ref lit) => {
+                let set = self.literal_set(lit).unwrap();
+                self.single(State::Char(set, HOLE))
+            },
+            Ast::Assertion(assertion) => self.single(State::Assert(assertion, HOLE)),
+            Ast::Concat(ref items) => {
+                if items.is_empty() {
+                    // A split whose arms both lead on stands in for the empty
+                    // sequence
+                    let state = self.push(State::Split(HOLE, HOLE));
+                    return Ok(Frag { start: state, outs: vec![state, state] });
+                }
+
+                let mut frag = self.compile(&items[0])?;
+                for item in &items[1..] {
+                    let next = self.compile(item)?;
+                    self.patch(&frag.outs, next.start);
+                    frag.outs = next.outs;
+                }
+                frag
+            },
+            Ast::Alternation(ref branches) => {
+                let mut frags = Vec::with_capacity(branches.len());
+                for branch in branches {
+                    frags.push(self.compile(branch)?);
+                }
+
+                let mut iter = frags.into_iter().rev();
+                let mut frag = iter.next().ok_or("Alternation without branches".to_string())?;
+                for branch in iter {
+                    let split = self.push(State::Split(branch.start, frag.start));
+                    let mut outs = branch.outs;
+                    outs.extend(frag.outs);
+                    frag = Frag { start: split, outs };
+                }
+                frag
+            },
+            Ast::Repetition(kind, ref inner) => {
+                let index = self.quantifiers.len();
+                let first = self.states.len();
+                self.quantifiers.push(first..first);
+
+                let inner = self.compile(inner)?;
+                let split = self.push(State::Split(inner.start, HOLE));
+                self.quantifiers[index].end = self.states.len();
+                match kind {
+                    Repetition::Maybe => {
+                        let mut outs = inner.outs;
+                        outs.push(split);
+                        Frag { start: split, outs }
+                    },
+                    Repetition::Always => {
+                        self.patch(&inner.outs, split);
+                        Frag { start: inner.start, outs: vec![split] }
+                    },
+                    Repetition::Any => {
+                        self.patch(&inner.outs, split);
+                        Frag { start: split, outs: vec![split] }
+                    },
+                }
+            },
+            Ast::Group(_, ref inner) => self.compile(inner)?,
+            Ast::Not(ref inner) => {
+                let set = self.set_of(inner)?.negate();
+                self.single(State::Char(set, HOLE))
+            },
+            Ast::Complement(_) | Ast::Intersection(_) if !self.automata => {
+                return Err("~(...) and & can only be matched by dfa::Dfa".to_string());
+            },
+            Ast::Complement(ref inner) => {
+                let dfa = self.operand(inner)?.complement();
+                self.embed(&dfa)
+            },
+            Ast::Intersection(ref operands) => {
+                let (first, rest) = operands.split_first()
+                    .ok_or("Intersection without operands".to_string())?;
+                let mut dfa = self.operand(first)?;
+                for operand in rest {
+                    dfa = dfa.intersection(&self.operand(operand)?)?;
+                }
+                self.embed(&dfa)
+            },
+        };
+        Ok(frag)
+    }
 }
 
 impl Graph {
     pub fn from_stream(parser: stream::TokenParser) -> Result<Graph, String> {
-        token_use!();
-
-        unimplemented!()
+        Graph::from_stream_with(parser, &Options::default())
     }
 
     pub fn from_string(pat: &str) -> Result<Graph, String> {
         let tokens = stream::TokenParser::from_string(pat)?;
         Graph::from_stream(tokens)
     }
+
+    pub fn from_string_with(pat: &str, options: &Options) -> Result<Graph, String> {
+        let tokens = stream::TokenParser::from_string(pat)?;
+        Graph::from_stream_with(tokens, options)
+    }
+
+    pub fn from_stream_with(parser: stream::TokenParser, options: &Options)
+        -> Result<Graph, String> {
+        Graph::from_tokens(parser.tokens(), options)
+    }
+
+    fn from_tokens(toks: &[Token], options: &Options) -> Result<Graph, String> {
+        Graph::from_ast(&Ast::from_tokens(toks)?, options)
+    }
+
+    pub fn from_ast(ast: &Ast, options: &Options) -> Result<Graph, String> {
+        Graph::compile(ast, options, false)
+    }
+
+    /// Compiles `ast`, with `Complement` and `And` groups if `automata` is set
+    pub(crate) fn compile(ast: &Ast, options: &Options, automata: bool) -> Result<Graph, String> {
+        Graph::compile_quantifiers(ast, options, automata).map(|(graph, _)| graph)
+    }
+
+    /// Like `compile`, also returning the states each quantifier compiled
+    /// to, in the order of their tokens
+    pub(crate) fn compile_quantifiers(ast: &Ast, options: &Options, automata: bool)
+        -> Result<(Graph, Vec<Range<usize>>), String> {
+        let mut compiler = Compiler {
+            states: Vec::new(),
+            options: *options,
+            automata,
+            quantifiers: Vec::new(),
+        };
+
+        let frag = compiler.compile(ast)?;
+        let matched = compiler.push(State::Match);
+        compiler.patch(&frag.outs, matched);
+
+        let graph = Graph {
+            states: compiler.states,
+            start: frag.start,
+            options: *options,
+        };
+        Ok((graph, compiler.quantifiers))
+    }
+
+    pub fn states(&self) -> &[State] {
+        &self.states
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Adds `state` and everything reachable from it without consuming input
+    /// to `set`, tagging each with the position where its match began.
+    fn add_thread(&self, set: &mut Vec<(usize, usize)>, state: usize, begin: usize,
+                  prev: Option<char>, next: Option<char>) {
+        if set.iter().any(|&(s, _)| s == state) {
+            return;
+        }
+        set.push((state, begin));
+
+        match self.states[state] {
+            State::Split(a, b) => {
+                self.add_thread(set, a, begin, prev, next);
+                self.add_thread(set, b, begin, prev, next);
+            },
+            State::Assert(assertion, next_state) => {
+                if self.options.assertion_holds(assertion, prev, next) {
+                    self.add_thread(set, next_state, begin, prev, next);
+                }
+            },
+            State::Char(..) | State::Match => (),
+        }
+    }
+
+    /// Finds the leftmost-longest match in `text`, as a byte range.
+    pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+        let mut current: Vec<(usize, usize)> = Vec::new();
+        let mut best: Option<(usize, usize)> = None;
+        let mut prev = None;
+        let mut chars = text.char_indices().peekable();
+
+        loop {
+            let (pos, next) = match chars.peek() {
+                Some(&(pos, c)) => (pos, Some(c)),
+                None => (text.len(), None),
+            };
+
+            if best.is_none() {
+                self.add_thread(&mut current, self.start, pos, prev, next);
+            }
+
+            for &(state, begin) in &current {
+                if let State::Match = self.states[state] {
+                    if best.is_none_or(|(b, e)| begin < b || (begin == b && pos > e)) {
+                        best = Some((begin, pos));
+                    }
+                }
+            }
+            if let Some((b, _)) = best {
+                current.retain(|&(_, begin)| begin <= b);
+            }
+
+            let c = match next {
+                Some(c) => c,
+                None => break,
+            };
+            chars.next();
+            let after = chars.peek().map(|&(_, c)| c);
+
+            let mut stepped = Vec::new();
+            for &(state, begin) in &current {
+                if let State::Char(ref set, next_state) = self.states[state] {
+                    if set.contains(c) {
+                        self.add_thread(&mut stepped, next_state, begin, Some(c), after);
+                    }
+                }
+            }
+            current = stepped;
+            prev = Some(c);
+
+            if current.is_empty() && best.is_some() {
+                break;
+            }
+        }
+
+        best
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(text).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod matching {
+        use graph::Graph;
+
+        #[test]
+        fn literal_search() {
+            let graph = Graph::from_string("bc").unwrap();
+            assert_eq!(Some((1, 3)), graph.find("abcd"));
+            assert!(!graph.is_match("acbd"));
+        }
+
+        #[test]
+        fn leftmost_longest() {
+            let graph = Graph::from_string("a(b|bc)*").unwrap();
+            assert_eq!(Some((1, 6)), graph.find("xabcbb"));
+        }
+
+        #[test]
+        fn quantifiers() {
+            let graph = Graph::from_string("^ab?c+d*$").unwrap();
+            assert!(graph.is_match("acc"));
+            assert!(graph.is_match("abcdd"));
+            assert!(!graph.is_match("abd"));
+            assert!(!graph.is_match("xac"));
+        }
+
+        #[test]
+        fn brackets_and_classes() {
+            let graph = Graph::from_string(r"^[^a-c][[:digit:]x]\W$").unwrap();
+            assert!(graph.is_match("d5!"));
+            assert!(graph.is_match("dx "));
+            assert!(!graph.is_match("b5!"));
+            assert!(!graph.is_match("d5a"));
+        }
+
+        #[test]
+        fn empty_branch() {
+            let graph = Graph::from_string("^a(b|)c$").unwrap();
+            assert!(graph.is_match("abc"));
+            assert!(graph.is_match("ac"));
+        }
+    }
+
+    mod case_insensitive {
+        use graph::{Graph, Options};
+
+        fn matcher(pat: &str) -> Graph {
+            let mut options = Options::new();
+            options.case_insensitive(true);
+            Graph::from_string_with(pat, &options).unwrap()
+        }
+
+        #[test]
+        fn literal() {
+            let graph = matcher("^straße$");
+            assert!(graph.is_match("STRAßE"));
+            assert!(graph.is_match("Straße"));
+            assert!(!Graph::from_string("^straße$").unwrap().is_match("STRAßE"));
+        }
+
+        #[test]
+        fn range() {
+            let graph = matcher("^[a-f]+$");
+            assert!(graph.is_match("aBcDeF"));
+            assert!(!graph.is_match("g"));
+
+            let greek = matcher("^[α-ω]$");
+            assert!(greek.is_match("Σ"));
+            assert!(greek.is_match("ς"));
+        }
+
+        #[test]
+        fn simple_folds() {
+            let graph = matcher("^k$");
+            assert!(graph.is_match("\u{212A}"));
+            assert!(matcher("^[[:lower:]]$").is_match("\u{17F}")); // long s
+            // Dotless i only folds to `I` in Turkic locales
+            assert!(!matcher("^i$").is_match("\u{131}"));
+            assert!(!matcher("^I$").is_match("\u{131}"));
+        }
+
+        #[test]
+        fn negated() {
+            let graph = matcher("^[^a]$");
+            assert!(!graph.is_match("A"));
+            assert!(graph.is_match("b"));
+        }
+    }
+
+    mod lines {
+        use graph::{Graph, Options};
+
+        fn matcher(pat: &str, options: &Options) -> Graph {
+            Graph::from_string_with(pat, options).unwrap()
+        }
+
+        #[test]
+        fn anchors_at_text_edges() {
+            let graph = matcher("^b$", &Options::new());
+            assert!(graph.is_match("b"));
+            assert!(!graph.is_match("a\nb\nc"));
+        }
+
+        #[test]
+        fn anchors_at_every_line() {
+            let mut options = Options::new();
+            options.multiline(true);
+            let graph = matcher("^b$", &options);
+            assert_eq!(Some((2, 3)), graph.find("a\nb\nc"));
+            assert!(!graph.is_match("a\r\nb\r\nc"));
+        }
+
+        #[test]
+        fn crlf_anchors() {
+            let mut options = Options::new();
+            options.multiline(true).crlf(true);
+            let graph = matcher("^b$", &options);
+            assert_eq!(Some((3, 4)), graph.find("a\r\nb\r\nc"));
+            assert_eq!(Some((2, 3)), graph.find("a\rb\rc"));
+
+            // Never between the \r and \n of one terminator
+            assert!(!matcher("\r$", &options).is_match("a\r\nb"));
+            assert!(!matcher("^\n", &options).is_match("a\r\nb"));
+            assert!(matcher("^\n", &options).is_match("a\n\nb"));
+        }
+
+        #[test]
+        fn dot() {
+            let default = Options::new();
+            assert!(!matcher("a.b", &default).is_match("a\nb"));
+            assert!(matcher("a.b", &default).is_match("a\rb"));
+
+            let mut crlf = Options::new();
+            crlf.crlf(true);
+            assert!(!matcher("a.b", &crlf).is_match("a\rb"));
+
+            let mut dot_all = Options::new();
+            dot_all.dot_all(true).crlf(true);
+            assert!(matcher("a.b", &dot_all).is_match("a\nb"));
+            assert!(matcher("a.b", &dot_all).is_match("a\rb"));
+        }
+    }
 }
@@ -0,0 +1,202 @@
+//! Bounds on the length of a pattern's matches, worked out from its tokens.
+//!
+//! ```
+//! use toy_regex::length::length;
+//! use toy_regex::stream::TokenParser;
+//!
+//! let len = length(TokenParser::from_string(r"v\d(\.\d+)?").unwrap().tokens()).unwrap();
+//! assert_eq!((2, None), (len.min, len.max));
+//! assert_eq!(None, len.fixed());
+//! ```
+
+use ast::Repetition;
+use stream::{Literal, Token};
+use validate::TokenError;
+use visit::{walk, Visitor};
+
+/// Bounds on the number of characters a pattern matches
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Length {
+    pub min: usize,
+    /// `None` if there is no bound
+    pub max: Option<usize>,
+}
+
+impl Length {
+    fn exactly(len: usize) -> Length {
+        Length { min: len, max: Some(len) }
+    }
+
+    /// The length of every match, if it's always the same, as lookbehinds
+    /// need
+    pub fn fixed(&self) -> Option<usize> {
+        Some(self.min).filter(|&min| self.max == Some(min))
+    }
+
+    fn then(self, other: Length) -> Length {
+        Length {
+            min: self.min.saturating_add(other.min),
+            max: self.max.and_then(|max| other.max.and_then(|other| max.checked_add(other))),
+        }
+    }
+
+    fn or(self, other: Length) -> Length {
+        Length {
+            min: self.min.min(other.min),
+            max: self.max.and_then(|max| other.max.map(|other| max.max(other))),
+        }
+    }
+
+    fn and(self, other: Length) -> Length {
+        let max = match (self.max, other.max) {
+            (Some(max), Some(other)) => Some(max.min(other)),
+            (max, None) | (None, max) => max,
+        };
+        Length { min: self.min.max(other.min), max }
+    }
+}
+
+/// A group being measured: the branches or operands so far, and the
+/// sequence since the last delimiter
+struct Measuring {
+    done: Option<Length>,
+    sequence: Length,
+}
+
+struct Lengths(Vec<Measuring>);
+
+impl Lengths {
+    fn enter(&mut self) {
+        self.0.push(Measuring { done: None, sequence: Length::exactly(0) });
+    }
+
+    fn delimiter(&mut self, combine: fn(Length, Length) -> Length) {
+        let top = self.0.last_mut().unwrap();
+        top.done = Some(top.done.map_or(top.sequence, |done| combine(done, top.sequence)));
+        top.sequence = Length::exactly(0);
+    }
+
+    /// Closes the innermost group, giving its length to the one around it
+    fn exit(&mut self, combine: fn(Length, Length) -> Length, length: fn(Length) -> Length) {
+        self.delimiter(combine);
+        let group = length(self.0.pop().unwrap().done.unwrap());
+        if let Some(outer) = self.0.last_mut() {
+            outer.sequence = outer.sequence.then(group);
+        } else {
+            self.0.push(Measuring { done: Some(group), sequence: Length::exactly(0) });
+        }
+    }
+
+    fn sequence(&mut self, length: fn(Length) -> Length) {
+        self.exit(Length::then, length);
+    }
+}
+
+impl Visitor for Lengths {
+    fn enter_pat(&mut self, index: usize) { self.enter() }
+    fn exit_pat(&mut self, index: usize) { self.sequence(|len| len) }
+    fn enter_capture(&mut self, name: Option<&str>, index: usize) { self.enter() }
+    fn exit_capture(&mut self, name: Option<&str>, index: usize) { self.sequence(|len| len) }
+    fn enter_not(&mut self, index: usize) { self.enter() }
+    fn exit_not(&mut self, index: usize) { self.sequence(|_| Length::exactly(1)) }
+    fn enter_repetition(&mut self, kind: Repetition, index: usize) { self.enter() }
+    fn exit_repetition(&mut self, kind: Repetition, index: usize) {
+        match kind {
+            Repetition::Maybe => self.sequence(|len| Length { min: 0, max: len.max }),
+            // Repeating something empty is still empty
+            Repetition::Always => self.sequence(|len| {
+                Length { min: len.min, max: len.max.filter(|&max| max == 0) }
+            }),
+            Repetition::Any => self.sequence(|len| {
+                Length { min: 0, max: len.max.filter(|&max| max == 0) }
+            }),
+        }
+    }
+    fn enter_or(&mut self, index: usize) { self.enter() }
+    fn or_delimiter(&mut self, index: usize) { self.delimiter(Length::or) }
+    fn exit_or(&mut self, index: usize) { self.exit(Length::or, |len| len) }
+    fn enter_complement(&mut self, index: usize) { self.enter() }
+    fn exit_complement(&mut self, index: usize) {
+        self.sequence(|_| Length { min: 0, max: None })
+    }
+    fn enter_and(&mut self, index: usize) { self.enter() }
+    fn and_delimiter(&mut self, index: usize) { self.delimiter(Length::and) }
+    fn exit_and(&mut self, index: usize) { self.exit(Length::and, |len| len) }
+    fn literal(&mut self, lit: &Literal, index: usize) {
+        let len = match *lit {
+            Literal::StartOfLine | Literal::EndOfLine
+                | Literal::StartOfText | Literal::EndOfText => 0,
+            _ => 1,
+        };
+        let top = self.0.last_mut().unwrap();
+        top.sequence = top.sequence.then(Length::exactly(len));
+    }
+}
+
+/// The shortest and longest matches a pattern could have, in characters,
+/// with `Maybe`, `Any` and `Always` counting for 0 to 1, 0 to unbounded and
+/// 1 to unbounded times their content.
+///
+/// The bounds come from the tokens alone, so they can be loose: `a&b` is
+/// counted as 1 long though it matches nothing, and `~(...)` as anything
+/// from 0 to unbounded.
+pub fn length(toks: &[Token]) -> Result<Length, TokenError> {
+    let mut lengths = Lengths(Vec::new());
+    walk(toks, &mut lengths)?;
+    Ok(lengths.0.pop().and_then(|pat| pat.done).unwrap_or(Length::exactly(0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use dfa::Dfa;
+    use graph::Graph;
+    use length::{length, Length};
+    use stream::{ParserConfig, TokenParser};
+
+    fn tokens(pat: &str) -> Vec<::stream::Token> {
+        TokenParser::from_string(pat).unwrap().into_tokens()
+    }
+
+    #[test]
+    fn lengths() {
+        let bounds = |pat: &str| {
+            let mut config = ParserConfig::new();
+            config.operators(true);
+            let len = length(TokenParser::from_string_with(pat, &config).unwrap().tokens()).unwrap();
+            (len.min, len.max)
+        };
+        assert_eq!((0, Some(0)), bounds(""));
+        assert_eq!((3, Some(3)), bounds(r"^a\d[^x-z]$"));
+        assert_eq!((1, Some(3)), bounds("a|(bc)d?"));
+        assert_eq!((0, Some(3)), bounds("a|(bc)d?|"));
+        assert_eq!((2, None), bounds("ab+c*"));
+        assert_eq!((0, Some(0)), bounds("(^)*$+"));
+        assert_eq!((3, Some(4)), bounds("(a+&..c?)b"));
+        assert_eq!((1, None), bounds("x~(a)"));
+    }
+
+    #[test]
+    fn fixed_lengths() {
+        assert_eq!(Some(3), length(&tokens(r"(ab|cd)\w")).unwrap().fixed());
+        assert_eq!(Some(0), length(&tokens("^")).unwrap().fixed());
+        assert_eq!(None, length(&tokens("ab?")).unwrap().fixed());
+        assert_eq!(None, length(&tokens("a*")).unwrap().fixed());
+        assert_eq!(Length { min: 2, max: None }.fixed(), None);
+    }
+
+    proptest! {
+        #[test]
+        fn matches_fit_the_length(pat in r"[ab|()*+?^$]{0,10}", texts in prop::collection::vec("[ab]{0,8}", 8)) {
+            if let Ok(parser) = TokenParser::from_string(&pat) {
+                let len = length(parser.tokens()).unwrap();
+                let dfa = Dfa::anchored(&Graph::from_stream(parser).unwrap()).unwrap();
+                for text in texts.iter().filter(|text| dfa.is_match(text)) {
+                    prop_assert!(len.min <= text.len(), "{} on {}", pat, text);
+                    prop_assert!(len.max.is_none_or(|max| text.len() <= max), "{} on {}", pat, text);
+                }
+            }
+        }
+    }
+}
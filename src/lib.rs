@@ -8,11 +8,46 @@ extern crate itertools;
 
 extern crate typed_arena;
 
+extern crate num_bigint;
+
+#[cfg(feature = "rand")]
+extern crate rand;
+
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+
+#[cfg(test)]
 #[macro_use]
-mod stream;
+extern crate proptest;
+
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+#[macro_use]
+pub mod stream;
 // use stream;
 
-mod graph;
+mod case_folding;
+mod glob;
+mod posix;
+mod printer;
+mod scc;
+mod sql;
+pub mod ast;
+pub mod charset;
+pub mod dead;
+pub mod dfa;
+pub mod generate;
+pub mod graph;
+pub mod length;
+pub mod pattern;
+pub mod redos;
+pub mod simplify;
+pub mod validate;
+pub mod visit;
+
+pub use stream::{escape, to_pattern};
 
 #[cfg(test)]
 mod tests {
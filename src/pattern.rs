@@ -0,0 +1,289 @@
+//! Building patterns in code instead of formatting and parsing strings.
+//!
+//! ```
+//! use toy_regex::pattern::Pattern;
+//! use toy_regex::stream::Literal;
+//!
+//! let version = Pattern::lit("v")
+//!     .then(Pattern::class(Literal::Digit).one_or_more().capture("major"))
+//!     .then(Pattern::lit(".").then(Pattern::class(Literal::Digit).one_or_more()).maybe());
+//! assert_eq!(Ok(r"v(:<major>\d+)(\.\d+)?".to_string()), version.to_pattern());
+//! ```
+
+use std::convert::TryFrom;
+
+use ast::{self, Ast, Repetition};
+use graph::Assertion;
+use stream::{self, Token, TokenParser};
+use validate::validate;
+
+/// A pattern under construction. Every method builds a tree rather than a
+/// token list, so the tokens it turns into are always balanced.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Pattern {
+    ast: Ast,
+}
+
+impl Pattern {
+    /// Matches the empty string
+    pub fn empty() -> Pattern {
+        Pattern { ast: Ast::Concat(Vec::new()) }
+    }
+
+    /// Matches `text` exactly. Nothing in it is special.
+    pub fn lit(text: &str) -> Pattern {
+        Pattern { ast: ast::concat(text.chars().map(Ast::Literal).collect()) }
+    }
+
+    /// Matches a single literal token, e.g. `Literal::Digit` or
+    /// `Literal::AnyChar`. A `Range` whose begin is after its end has its
+    /// bounds swapped, and so do the ranges of a `Class`.
+    pub fn class(lit: stream::Literal) -> Pattern {
+        use stream::Literal::*;
+        let ast = match lit {
+            Char(c) => Ast::Literal(c),
+            StartOfLine => Ast::Assertion(Assertion::StartOfLine),
+            EndOfLine => Ast::Assertion(Assertion::EndOfLine),
+            StartOfText => Ast::Assertion(Assertion::StartOfText),
+            EndOfText => Ast::Assertion(Assertion::EndOfText),
+            Range { begin, end } if begin > end => Ast::Class(Range { begin: end, end: begin }),
+            Class { name, ranges } => Ast::Class(Class {
+                name,
+                ranges: ranges.into_iter()
+                    .map(|(begin, end)| if begin > end { (end, begin) } else { (begin, end) })
+                    .collect(),
+            }),
+            lit => Ast::Class(lit),
+        };
+        Pattern { ast }
+    }
+
+    /// Any character from `begin` to `end` inclusive, in either order
+    pub fn range(begin: char, end: char) -> Pattern {
+        Pattern::class(stream::Literal::Range { begin, end })
+    }
+
+    pub fn any_char() -> Pattern {
+        Pattern::class(stream::Literal::AnyChar)
+    }
+
+    pub fn start_of_line() -> Pattern {
+        Pattern::class(stream::Literal::StartOfLine)
+    }
+
+    pub fn end_of_line() -> Pattern {
+        Pattern::class(stream::Literal::EndOfLine)
+    }
+
+    /// `self` followed by `next`
+    pub fn then(self, next: Pattern) -> Pattern {
+        let mut items = match self.ast {
+            Ast::Concat(items) => items,
+            ast => vec![ast],
+        };
+        match next.ast {
+            Ast::Concat(more) => items.extend(more),
+            ast => items.push(ast),
+        }
+        Pattern { ast: ast::concat(items) }
+    }
+
+    /// Either `self` or `other`
+    pub fn or(self, other: Pattern) -> Pattern {
+        let mut branches = match self.ast {
+            Ast::Alternation(branches) => branches,
+            ast => vec![ast],
+        };
+        match other.ast {
+            Ast::Alternation(more) => branches.extend(more),
+            ast => branches.push(ast),
+        }
+        Pattern { ast: Ast::Alternation(branches) }
+    }
+
+    /// Any character that `self` doesn't match. `self` has to be a set of
+    /// single characters: a literal character, a class or range, an
+    /// alternation of those, or another negation.
+    pub fn negate(self) -> Result<Pattern, String> {
+        if !is_set(&self.ast) {
+            return Err(format!("{:?} isn't a set of characters", self.ast));
+        }
+        Ok(Pattern { ast: Ast::Not(Box::new(self.ast)) })
+    }
+
+    /// A named capture group around `self`
+    pub fn capture(self, name: &str) -> Pattern {
+        Pattern { ast: Ast::Group(Some(name.to_string()), Box::new(self.ast)) }
+    }
+
+    /// An unnamed capture group around `self`
+    pub fn group(self) -> Pattern {
+        Pattern { ast: Ast::Group(None, Box::new(self.ast)) }
+    }
+
+    /// `?`
+    pub fn maybe(self) -> Pattern {
+        self.repeat(Repetition::Maybe)
+    }
+
+    /// `+`
+    pub fn one_or_more(self) -> Pattern {
+        self.repeat(Repetition::Always)
+    }
+
+    /// `*`
+    pub fn zero_or_more(self) -> Pattern {
+        self.repeat(Repetition::Any)
+    }
+
+    /// As in the native syntax, a quantifier applies to one item, so a
+    /// sequence gets an unnamed capture group around it first.
+    fn repeat(self, kind: Repetition) -> Pattern {
+        let operand = match self.ast {
+            ast @ Ast::Concat(_) => Ast::Group(None, Box::new(ast)),
+            ast => ast,
+        };
+        Pattern { ast: Ast::Repetition(kind, Box::new(operand)) }
+    }
+
+    pub fn ast(&self) -> &Ast {
+        &self.ast
+    }
+
+    /// The token stream, wrapped in `Pat`
+    pub fn to_tokens(&self) -> Vec<Token> {
+        self.ast.to_tokens()
+    }
+
+    /// The pattern in the native syntax, if it can be written in it (see
+    /// `stream::to_pattern`)
+    pub fn to_pattern(&self) -> Result<String, String> {
+        stream::to_pattern(&self.to_tokens())
+    }
+
+    pub fn into_parser(self) -> TokenParser {
+        TokenParser::from_pattern(&self)
+    }
+}
+
+/// Whether `ast` matches one character out of a set, so that it can be
+/// negated
+fn is_set(ast: &Ast) -> bool {
+    match *ast {
+        Ast::Literal(_) | Ast::Class(_) => true,
+        Ast::Alternation(ref branches) => branches.iter().all(is_set),
+        Ast::Not(ref inner) => is_set(inner),
+        _ => false,
+    }
+}
+
+/// Whether everything `ast` negates is a set of characters
+fn negates_sets(ast: &Ast) -> bool {
+    match *ast {
+        Ast::Literal(_) | Ast::Class(_) | Ast::Assertion(_) => true,
+        Ast::Not(ref inner) => is_set(inner),
+        Ast::Repetition(_, ref inner) | Ast::Group(_, ref inner) | Ast::Complement(ref inner) => {
+            negates_sets(inner)
+        },
+        Ast::Concat(ref items) | Ast::Alternation(ref items) | Ast::Intersection(ref items) => {
+            items.iter().all(negates_sets)
+        },
+    }
+}
+
+/// Takes a tree built by hand, after checking it the way the builder
+/// methods would: its tokens have to be well-formed (see the `validate`
+/// module) and everything it negates a set of characters.
+impl TryFrom<Ast> for Pattern {
+    type Error = String;
+
+    fn try_from(ast: Ast) -> Result<Pattern, String> {
+        validate(&ast.to_tokens())?;
+        if !negates_sets(&ast) {
+            return Err("Only sets of characters can be negated".to_string());
+        }
+        Ok(Pattern { ast })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::Graph;
+    use pattern::Pattern;
+    use stream::{Literal, TokenParser};
+
+    fn same_as(pattern: &Pattern, pat: &str) {
+        assert_eq!(TokenParser::from_string(pat).unwrap().tokens(), pattern.to_tokens());
+    }
+
+    #[test]
+    fn sequences_and_alternatives() {
+        same_as(&Pattern::lit("abc"), "abc");
+        same_as(&Pattern::lit("a.b"), r"a\.b");
+        same_as(&Pattern::lit("ab").or(Pattern::lit("c")).or(Pattern::empty()), "ab|c|");
+        same_as(&Pattern::lit("a").then(Pattern::lit("b").or(Pattern::lit("c")).group()), "a(b|c)");
+        same_as(&Pattern::empty().then(Pattern::lit("a")), "a");
+    }
+
+    #[test]
+    fn quantifiers_and_groups() {
+        let number = Pattern::class(Literal::Digit).one_or_more().capture("n");
+        same_as(&number, r"(:<n>\d+)");
+        same_as(&Pattern::lit("ab").zero_or_more(), "(ab)*");
+        same_as(&Pattern::lit("a").zero_or_more().maybe(), "a*?");
+        same_as(&Pattern::class(Literal::Alnum).negate().unwrap(), r"\W");
+        same_as(&Pattern::start_of_line().then(Pattern::any_char()).then(Pattern::end_of_line()), "^.$");
+    }
+
+    #[test]
+    fn builds_parsers_and_matchers() {
+        let pattern = Pattern::range('a', 'f').or(Pattern::class(Literal::Digit)).one_or_more();
+        assert_eq!(Ok("[a-f[:digit:]]+".to_string()), pattern.to_pattern());
+
+        let parser = pattern.into_parser();
+        let graph = Graph::from_stream(parser).unwrap();
+        assert!(graph.is_match("c0ffee"));
+        assert!(!graph.is_match("xyz"));
+    }
+
+    #[test]
+    fn backwards_range() {
+        assert_eq!(Pattern::range('a', 'z'), Pattern::range('z', 'a'));
+        assert_eq!(Pattern::range('a', 'z'), Pattern::class(Literal::Range { begin: 'z', end: 'a' }));
+        let class = |ranges: Vec<(char, char)>| Pattern::class(Literal::Class {
+            name: "c".to_string(),
+            ranges,
+        });
+        assert_eq!(class(vec![('a', 'c'), ('x', 'z')]), class(vec![('c', 'a'), ('x', 'z')]));
+    }
+
+    #[test]
+    fn checked_trees() {
+        use std::convert::TryFrom;
+        use ast::{Ast, Repetition};
+
+        let digits = Ast::Repetition(Repetition::Always, Box::new(Ast::Class(Literal::Digit)));
+        assert_eq!(Pattern::class(Literal::Digit).one_or_more(), Pattern::try_from(digits).unwrap());
+
+        let backwards = Ast::Class(Literal::Range { begin: 'z', end: 'a' });
+        assert!(Pattern::try_from(backwards).is_err());
+        let sequence = Ast::Repetition(Repetition::Any,
+            Box::new(Ast::Concat(vec![Ast::Literal('a'), Ast::Literal('b')])));
+        assert!(Pattern::try_from(sequence).is_err());
+        let not_a_set = Ast::Not(Box::new(Ast::Concat(vec![Ast::Literal('a'), Ast::Literal('b')])));
+        assert!(Pattern::try_from(not_a_set).is_err());
+    }
+
+    #[test]
+    fn negate_sets_only() {
+        let vowel = Pattern::lit("a").or(Pattern::lit("e")).or(Pattern::range('x', 'z'));
+        same_as(&vowel.negate().unwrap(), "[^aex-z]");
+        assert!(Pattern::class(Literal::Digit).negate().unwrap().negate().is_ok());
+
+        assert!(Pattern::lit("ab").negate().is_err());
+        assert!(Pattern::lit("a").maybe().negate().is_err());
+        assert!(Pattern::start_of_line().negate().is_err());
+        assert!(Pattern::lit("a").or(Pattern::empty()).negate().is_err());
+        assert!(Pattern::lit("a").group().negate().is_err());
+    }
+}
@@ -0,0 +1,433 @@
+//! Strict POSIX basic (BRE) and extended (ERE) regular expression syntax,
+//! as specified in XBD chapter 9. Both produce the same token stream as the
+//! native syntax; constructs POSIX leaves undefined are rejected.
+
+use stream::{wrap_span, Cursor, ParserConfig, Span, Spanned, Token};
+
+/// Characters a backslash makes literal in a BRE
+const BRE_SPECIAL: &[char] = &['.', '[', '\\', '*', '^', '$'];
+/// Characters a backslash makes literal in an ERE
+const ERE_SPECIAL: &[char] = &[
+    '^', '.', '[', '$', '(', ')', '|', '*', '+', '?', '{', '\\'
+];
+
+/// `RE_DUP_MAX`, the largest count allowed in an interval
+pub(crate) const DUP_MAX: u32 = 255;
+
+/// The most tokens an interval may expand to. Nested intervals multiply, so
+/// `DUP_MAX` alone doesn't keep expansions small.
+pub(crate) const EXPANSION_LIMIT: usize = 100_000;
+
+/// Reads `m`, `m,` or `m,n` and the `close` delimiter of an interval whose
+/// opening delimiter was just consumed
+pub(crate) fn interval(chars: &mut Cursor, close: &str) -> Result<(u32, Option<u32>), String> {
+    let min = chars.number().ok_or("Interval has no minimum count".to_string())?;
+    let max = if chars.eat(",") {
+        chars.number()
+    } else {
+        Some(min)
+    };
+
+    if !chars.eat(close) {
+        return Err("Unclosed interval".to_string());
+    }
+
+    if min > DUP_MAX || max.is_some_and(|max| max > DUP_MAX) {
+        return Err(format!("Interval counts can't exceed {}", DUP_MAX));
+    }
+    if max.is_some_and(|max| max < min) {
+        return Err("Interval minimum is larger than its maximum".to_string());
+    }
+    Ok((min, max))
+}
+
+/// `toks` with every capture group turned into a single branch `Or`, which
+/// groups the same way without capturing
+fn uncaptured(toks: &Spanned) -> Spanned {
+    token_use!();
+    toks.iter().map(|(tok, span)| {
+        let tok = match *tok {
+            Grouping(Begin(GBegin::Cap { .. })) => Grouping(Begin(GBegin::Or)),
+            Grouping(End(GEnd::Cap)) => Grouping(End(GEnd::Or)),
+            ref tok => tok.clone(),
+        };
+        (tok, span.clone())
+    }).collect()
+}
+
+/// Expands the interval `{min,max}` over `operand` using the quantifiers the
+/// token stream has: `a{2,4}` becomes `aa(aa?)?` and `a{2,}` becomes `aa+`,
+/// where the parentheses are a single branch `Or`. `operand` has to be a
+/// single item. Only the first copy of it keeps its capture groups, so the
+/// interval doesn't add any. The copies keep its spans, and the groups the
+/// interval adds carry `span`.
+///
+/// Fails if the copies would take more than `EXPANSION_LIMIT` tokens.
+pub(crate) fn repeat(operand: &Spanned, min: u32, max: Option<u32>, span: Span)
+    -> Result<Spanned, String> {
+    use stream::{GBegin, GEnd};
+    let copies = max.unwrap_or(min).max(1) as usize;
+    if operand.len().saturating_mul(copies) > EXPANSION_LIMIT {
+        return Err(format!("Interval expansion too large: more than {} tokens", EXPANSION_LIMIT));
+    }
+
+    let plain = uncaptured(operand);
+    let copy = |i: u32| if i == 0 { operand.clone() } else { plain.clone() };
+    let mut toks = Vec::new();
+
+    let required = match max {
+        None if min > 0 => min - 1,
+        _ => min,
+    };
+    for i in 0..required {
+        toks.extend(copy(i));
+    }
+
+    match max {
+        None if min == 0 => toks.extend(wrap_span(GBegin::Any, GEnd::Any, span, copy(required))),
+        None => toks.extend(wrap_span(GBegin::Always, GEnd::Always, span, copy(required))),
+        Some(max) => {
+            // Built from the innermost, last, copy out
+            let mut optional: Spanned = Vec::new();
+            for i in (min..max).rev() {
+                // A quantifier takes one item, so more than one needs a group
+                let inner = if optional.is_empty() {
+                    copy(i)
+                } else {
+                    let mut inner = copy(i);
+                    inner.extend(optional);
+                    wrap_span(GBegin::Or, GEnd::Or, span.clone(), inner)
+                };
+                optional = wrap_span(GBegin::Maybe, GEnd::Maybe, span.clone(), inner);
+            }
+            toks.extend(optional);
+        },
+    }
+
+    Ok(toks)
+}
+
+struct Parser<'a> {
+    chars: Cursor<'a>,
+    config: &'a ParserConfig,
+}
+
+impl<'a> Parser<'a> {
+    /// Reads what follows a `\` at `at`
+    fn escaped(&mut self, at: usize, special: &[char]) -> Result<Spanned, String> {
+        token_use!();
+        match self.chars.next() {
+            Some(c) if special.contains(&c) => Ok(vec![(Literal(Char(c)), at..self.chars.pos)]),
+            Some(c) if c.is_ascii_digit() => Err("Back-references not supported".to_string()),
+            Some(c) => Err(format!("Escape \\{} is undefined in POSIX", c)),
+            None => Err("Pattern ended when expecting escaped character".to_string()),
+        }
+    }
+
+    /// A single character token for the `c` read from `at`
+    fn single(&self, lit: ::stream::Literal, at: usize) -> Spanned {
+        vec![(Token::Literal(lit), at..self.chars.pos)]
+    }
+
+    /// BRE: a sequence of atoms up to the end of the pattern or, inside a
+    /// subexpression, up to `\)`.
+    fn bre_sequence(&mut self, in_group: bool) -> Result<Spanned, String> {
+        token_use!();
+        let mut toks = Vec::new();
+
+        // A leading `^` is an anchor, and a `*` right after it or at the
+        // start is a literal
+        if self.chars.peek() == Some('^') {
+            self.chars.next();
+            toks.extend(self.single(StartOfLine, self.chars.pos - 1));
+        }
+        if self.chars.peek() == Some('*') {
+            self.chars.next();
+            toks.extend(self.single(Char('*'), self.chars.pos - 1));
+        }
+
+        loop {
+            if self.chars.rest().is_empty() {
+                if in_group {
+                    return Err("Pattern ended with unclosed groups".to_string());
+                }
+                return Ok(toks);
+            }
+            if self.chars.starts_with("\\)") {
+                if in_group {
+                    return Ok(toks);
+                }
+                return Err("Group closed without being opened".to_string());
+            }
+
+            let at = self.chars.pos;
+            let mut atom = if self.chars.eat("\\(") {
+                let inner = self.bre_sequence(true)?;
+                self.chars.eat("\\)");
+                wrap_span(GBegin::Cap { name: None }, GEnd::Cap, at..self.chars.pos, inner)
+            } else if self.chars.starts_with("\\{") {
+                return Err("Interval has no operand".to_string());
+            } else {
+                match self.chars.next().unwrap() {
+                    '\\' => self.escaped(at, BRE_SPECIAL)?,
+                    '[' => self.chars.bracket(self.config)?,
+                    '.' => self.single(AnyChar, at),
+                    // `$` is only an anchor at the end of the pattern or of
+                    // a subexpression
+                    '$' if self.chars.rest().is_empty()
+                        || (in_group && self.chars.starts_with("\\)")) => self.single(EndOfLine, at),
+                    c => self.single(Char(c), at),
+                }
+            };
+
+            loop {
+                if self.chars.peek() == Some('*') {
+                    self.chars.next();
+                    atom = wrap_span(GBegin::Any, GEnd::Any, at..self.chars.pos, atom);
+                } else if self.chars.eat("\\{") {
+                    let (min, max) = interval(&mut self.chars, "\\}")?;
+                    atom = repeat(&atom, min, max, at..self.chars.pos)?;
+                } else {
+                    break;
+                }
+            }
+            toks.extend(atom);
+        }
+    }
+
+    /// ERE: `|` separated branches up to the end of the pattern or, inside a
+    /// group, up to `)`.
+    fn ere_alternation(&mut self, in_group: bool) -> Result<Spanned, String> {
+        token_use!();
+        let start = self.chars.pos;
+        let mut branches = vec![self.ere_branch(in_group)?];
+        let mut delimiters = Vec::new();
+        while self.chars.peek() == Some('|') {
+            delimiters.push(self.chars.pos..self.chars.pos + 1);
+            self.chars.next();
+            branches.push(self.ere_branch(in_group)?);
+        }
+
+        if branches.len() == 1 {
+            return Ok(branches.pop().unwrap());
+        }
+
+        let mut toks = Vec::new();
+        for (i, branch) in branches.into_iter().enumerate() {
+            if i > 0 {
+                toks.push((Grouping(OrDelimiter), delimiters[i - 1].clone()));
+            }
+            toks.extend(branch);
+        }
+        Ok(wrap_span(GBegin::Or, GEnd::Or, start..self.chars.pos, toks))
+    }
+
+    fn ere_branch(&mut self, in_group: bool) -> Result<Spanned, String> {
+        token_use!();
+        let mut toks = Vec::new();
+
+        loop {
+            match self.chars.peek() {
+                None | Some('|') => break,
+                Some(')') if in_group => break,
+                _ => (),
+            }
+
+            let at = self.chars.pos;
+            let mut atom = match self.chars.next().unwrap() {
+                '(' => {
+                    let inner = self.ere_alternation(true)?;
+                    if self.chars.next() != Some(')') {
+                        return Err("Pattern ended with unclosed groups".to_string());
+                    }
+                    wrap_span(GBegin::Cap { name: None }, GEnd::Cap, at..self.chars.pos, inner)
+                },
+                '\\' => self.escaped(at, ERE_SPECIAL)?,
+                '[' => self.chars.bracket(self.config)?,
+                '.' => self.single(AnyChar, at),
+                '^' => self.single(StartOfLine, at),
+                '$' => self.single(EndOfLine, at),
+                c @ '*' | c @ '+' | c @ '?' | c @ '{' =>
+                    return Err(format!("Repetition operator {} has no operand", c)),
+                c => self.single(Char(c), at),
+            };
+
+            loop {
+                let (begin, end) = match self.chars.peek() {
+                    Some('*') => (GBegin::Any, GEnd::Any),
+                    Some('+') => (GBegin::Always, GEnd::Always),
+                    Some('?') => (GBegin::Maybe, GEnd::Maybe),
+                    Some('{') => {
+                        self.chars.next();
+                        let (min, max) = interval(&mut self.chars, "}")?;
+                        atom = repeat(&atom, min, max, at..self.chars.pos)?;
+                        continue;
+                    },
+                    _ => break,
+                };
+                self.chars.next();
+                atom = wrap_span(begin, end, at..self.chars.pos, atom);
+            }
+            toks.extend(atom);
+        }
+
+        if toks.is_empty() {
+            return Err("Empty subexpression is undefined in POSIX".to_string());
+        }
+        Ok(toks)
+    }
+}
+
+/// Parses a POSIX basic regular expression
+pub fn parse_bre(pat: &str, config: &ParserConfig) -> Result<Spanned, String> {
+    use stream::{GBegin, GEnd};
+    let mut parser = Parser { chars: Cursor::new(pat), config };
+    let toks = parser.bre_sequence(false)?;
+    Ok(wrap_span(GBegin::Pat, GEnd::Pat, 0..pat.len(), toks))
+}
+
+/// Parses a POSIX extended regular expression
+pub fn parse_ere(pat: &str, config: &ParserConfig) -> Result<Spanned, String> {
+    use stream::{GBegin, GEnd};
+    let mut parser = Parser { chars: Cursor::new(pat), config };
+    let toks = parser.ere_alternation(false)?;
+    Ok(wrap_span(GBegin::Pat, GEnd::Pat, 0..pat.len(), toks))
+}
+
+#[cfg(test)]
+mod tests {
+    use stream::{Dialect, ParserConfig, Token, TokenParser};
+
+    fn parse(pat: &str, dialect: Dialect) -> Result<Vec<Token>, String> {
+        let mut config = ParserConfig::new();
+        config.dialect(dialect);
+        TokenParser::from_string_with(pat, &config).map(|parser| parser.into_tokens())
+    }
+
+    fn native(pat: &str) -> Vec<Token> {
+        TokenParser::from_string(pat).unwrap().into_tokens()
+    }
+
+    /// The native tokens for `pat`, with every capture group after the
+    /// first `keep` made a single branch `Or`, as intervals repeat them
+    fn grouped(pat: &str, keep: usize) -> Vec<Token> {
+        use stream;
+        token_use!();
+        let mut caps = 0;
+        let mut open = Vec::new();
+        native(pat).into_iter().map(|tok| match tok {
+            Grouping(Begin(GBegin::Cap { name })) => {
+                caps += 1;
+                open.push(caps > keep);
+                if caps > keep { Grouping(Begin(GBegin::Or)) } else { Grouping(Begin(GBegin::Cap { name })) }
+            },
+            Grouping(Begin(begin)) => {
+                open.push(false);
+                Grouping(Begin(begin))
+            },
+            Grouping(End(end)) => match open.pop() {
+                Some(true) => Grouping(End(GEnd::Or)),
+                _ => Grouping(End(end)),
+            },
+            tok => tok,
+        }).collect()
+    }
+
+    mod bre {
+        use super::{grouped, native, parse};
+        use stream::Dialect::Bre;
+
+        #[test]
+        fn groups_and_star() {
+            assert_eq!(native("a(bc)*d"), parse(r"a\(bc\)*d", Bre).unwrap());
+        }
+
+        #[test]
+        fn ere_operators_are_literal() {
+            assert_eq!(native(r"a\+b\?\|c\(\){"), parse("a+b?|c(){", Bre).unwrap());
+        }
+
+        #[test]
+        fn leading_star_is_literal() {
+            assert_eq!(native(r"\*a"), parse("*a", Bre).unwrap());
+            assert_eq!(native(r"^\*a"), parse("^*a", Bre).unwrap());
+            assert_eq!(native(r"(\*a)"), parse(r"\(*a\)", Bre).unwrap());
+        }
+
+        #[test]
+        fn anchors_only_at_edges() {
+            assert_eq!(native(r"^a\^b\$c$"), parse("^a^b$c$", Bre).unwrap());
+            assert_eq!(native(r"(^a$)"), parse(r"\(^a$\)", Bre).unwrap());
+        }
+
+        #[test]
+        fn intervals() {
+            assert_eq!(grouped("aa(aa?)?", 0), parse(r"a\{2,4\}", Bre).unwrap());
+            assert_eq!(native("aaa+"), parse(r"a\{3,\}", Bre).unwrap());
+            assert_eq!(grouped("(bc)(bc)", 1), parse(r"\(bc\)\{2\}", Bre).unwrap());
+            assert_eq!(grouped("(b(c))(b(c))+", 2), parse(r"\(b\(c\)\)\{2,\}", Bre).unwrap());
+            assert_eq!(native("a*"), parse(r"a\{0,\}", Bre).unwrap());
+        }
+
+        #[test]
+        fn undefined_constructs() {
+            assert!(parse(r"a\{3,2\}", Bre).is_err());
+            assert!(parse(r"a\{256\}", Bre).is_err());
+            assert!(parse(r"\(\(a\{255\}\)\{255\}\)\{255\}", Bre).is_err());
+            assert!(parse(r"\{2\}", Bre).is_err());
+            assert!(parse(r"a\w", Bre).is_err());
+            assert_eq!(Err("Back-references not supported".to_string()),
+                parse(r"\(a\)\1", Bre));
+            assert!(parse(r"\(a", Bre).is_err());
+            assert!(parse(r"a\)", Bre).is_err());
+        }
+    }
+
+    mod ere {
+        use super::{grouped, native, parse};
+        use stream::Dialect::Ere;
+
+        #[test]
+        fn same_as_native() {
+            for pat in &["a(b|c)+d?", "a|b|c", "^[^a-c]x*$", r"a\.\|", "(a(b)c)|d"] {
+                assert_eq!(native(pat), parse(pat, Ere).unwrap());
+            }
+        }
+
+        #[test]
+        fn intervals() {
+            use stream;
+            token_use!();
+            assert_eq!(grouped("(ab)(ab)?", 1), parse("(ab){1,2}", Ere).unwrap());
+            let caps = parse("(a(b)){0,3}", Ere).unwrap().into_iter()
+                .filter(|tok| matches!(*tok, Grouping(Begin(GBegin::Cap { .. }))))
+                .count();
+            assert_eq!(2, caps);
+            assert_eq!(native("b"), parse("a{0}b", Ere).unwrap());
+        }
+
+        #[test]
+        fn unmatched_close_is_literal() {
+            assert_eq!(native(r"a\)"), parse("a)", Ere).unwrap());
+        }
+
+        #[test]
+        fn undefined_constructs() {
+            assert!(parse("*a", Ere).is_err());
+            assert!(parse("a|*b", Ere).is_err());
+            assert!(parse("a||b", Ere).is_err());
+            assert!(parse("()", Ere).is_err());
+            assert!(parse(r"\d", Ere).is_err());
+            assert!(parse("a{x}", Ere).is_err());
+            assert!(parse("(a", Ere).is_err());
+        }
+
+        #[test]
+        fn nested_intervals() {
+            assert!(parse("(a{255}){255}", Ere).is_ok());
+            assert_eq!(Err("Interval expansion too large: more than 100000 tokens".to_string()),
+                parse("((a{255}){255}){255}", Ere));
+            assert!(parse("((a{255}){255}){0,}", Ere).is_ok());
+        }
+    }
+}
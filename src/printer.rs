@@ -0,0 +1,273 @@
+//! Writes token streams back out in the native syntax. What can and can't
+//! be printed is documented on `stream::to_pattern`.
+
+use ast::{Ast, Repetition};
+use graph::Assertion;
+use stream::{self, Cursor, ParserConfig, Token};
+
+/// Prints a whole token stream, from `Begin(Pat)` to `End(Pat)`
+pub fn to_pattern(toks: &[Token]) -> Result<String, String> {
+    let ast = Ast::from_tokens(toks)?;
+    group_body(&ast)
+}
+
+/// The inside of a `Pat`, capture or `Complement` group, the only places a
+/// top level `|` can be written. Branches that only fit in a bracket
+/// expression, like a `Range`, fall back to one.
+fn group_body(ast: &Ast) -> Result<String, String> {
+    match *ast {
+        Ast::Alternation(ref branches) if branches.len() > 1 => {
+            match branches.iter().map(intersection).collect::<Result<Vec<_>, _>>() {
+                Ok(printed) => Ok(printed.join("|")),
+                Err(err) => atom(ast).map_err(|_| err),
+            }
+        },
+        _ => intersection(ast),
+    }
+}
+
+fn intersection(ast: &Ast) -> Result<String, String> {
+    match *ast {
+        Ast::Intersection(ref operands) if operands.len() > 1 => {
+            Ok(operands.iter().map(sequence).collect::<Result<Vec<_>, _>>()?.join("&"))
+        },
+        _ => sequence(ast),
+    }
+}
+
+fn sequence(ast: &Ast) -> Result<String, String> {
+    match *ast {
+        Ast::Concat(ref items) => items.iter().map(atom).collect(),
+        _ => atom(ast),
+    }
+}
+
+/// Prints something that a quantifier can apply to
+fn atom(ast: &Ast) -> Result<String, String> {
+    use stream::Literal::*;
+    let printed = match *ast {
+        Ast::Literal(c) => stream::escape(&c.to_string()),
+        Ast::Class(AnyChar) => ".".to_string(),
+        Ast::Class(Alnum) => r"\w".to_string(),
+        Ast::Class(Digit) => r"\d".to_string(),
+        Ast::Class(Whitespace) => r"\s".to_string(),
+        Ast::Class(ref lit) => {
+            return Err(format!("{:?} can only be written inside a bracket expression", lit));
+        },
+        Ast::Assertion(Assertion::StartOfLine) => "^".to_string(),
+        Ast::Assertion(Assertion::EndOfLine) => "$".to_string(),
+        Ast::Assertion(assertion) => {
+            return Err(format!("{:?} has no native syntax", assertion));
+        },
+        Ast::Concat(_) => return Err("Sequence where a single item is expected".to_string()),
+        Ast::Alternation(ref branches) => bracket(ast, branches, false)?,
+        Ast::Intersection(_) => {
+            return Err("Intersection needs a capture group to be written here".to_string());
+        },
+        Ast::Complement(ref inner) => format!("~({})", group_body(inner)?),
+        Ast::Repetition(kind, ref inner) => {
+            let suffix = match kind {
+                Repetition::Maybe => '?',
+                Repetition::Always => '+',
+                Repetition::Any => '*',
+            };
+            format!("{}{}", atom(inner)?, suffix)
+        },
+        Ast::Group(ref name, ref inner) => {
+            let body = group_body(inner)?;
+            match *name {
+                Some(ref name) if name.contains('>') => {
+                    return Err(format!("Capture group name {} contains >", name));
+                },
+                Some(ref name) => format!("(:<{}>{})", name, body),
+                None => format!("({})", body),
+            }
+        },
+        Ast::Not(ref inner) => match **inner {
+            Ast::Class(Alnum) => r"\W".to_string(),
+            Ast::Class(Digit) => r"\D".to_string(),
+            Ast::Class(Whitespace) => r"\S".to_string(),
+            Ast::Alternation(ref branches) => bracket(ast, branches, true)?,
+            _ => return Err(format!("{:?} can't be negated", inner)),
+        },
+    };
+    Ok(printed)
+}
+
+/// Writes an `Or` (or a `Not` around one) as a bracket expression.
+///
+/// The bracket grammar gives `]`, `^` and `-` different meanings depending
+/// on where they are, so rather than second-guessing it the result is
+/// parsed again and rejected if it doesn't give back the same tokens.
+fn bracket(ast: &Ast, branches: &[Ast], negated: bool) -> Result<String, String> {
+    use stream::Literal::*;
+    let mut config = ParserConfig::default();
+    let mut inner = String::new();
+    if negated {
+        inner.push('^');
+    }
+
+    for branch in branches {
+        match *branch {
+            Ast::Literal(c) => inner.push(c),
+            Ast::Class(Range { begin, end }) => {
+                inner.push(begin);
+                inner.push('-');
+                inner.push(end);
+            },
+            Ast::Class(Class { ref name, ref ranges }) => {
+                config.register_class(name, ranges)?;
+                inner.push_str(&format!("[:{}:]", name));
+            },
+            Ast::Class(ref lit) => match posix_name(lit) {
+                Some(name) => inner.push_str(&format!("[:{}:]", name)),
+                None => return Err(format!("{:?} can't be written inside a bracket expression", lit)),
+            },
+            _ => return Err("Alternation needs a capture group to be written here".to_string()),
+        }
+    }
+    inner.push(']');
+
+    let printed = format!("[{}", inner);
+    let mut expected = Vec::new();
+    ast.push_tokens(&mut expected);
+
+    let mut chars = Cursor::new(&printed);
+    chars.next();
+    match chars.bracket(&config) {
+        Ok(ref toks) if chars.rest().is_empty()
+            && toks.iter().map(|(tok, _)| tok).eq(expected.iter()) => Ok(printed),
+        _ => Err(format!("{} doesn't read back as the same bracket expression", printed)),
+    }
+}
+
+fn posix_name(lit: &stream::Literal) -> Option<&'static str> {
+    use stream::Literal::*;
+    let name = match *lit {
+        Alnum => "alnum",
+        Alpha => "alpha",
+        Blank => "blank",
+        Cntrl => "cntrl",
+        Digit => "digit",
+        Graph => "graph",
+        Lower => "lower",
+        Print => "print",
+        Punct => "punct",
+        Whitespace => "space",
+        Upper => "upper",
+        XDigit => "xdigit",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use pattern::Pattern;
+    use stream::{self, to_pattern, TokenParser};
+
+    fn reprint(pat: &str) -> String {
+        TokenParser::from_string(pat).unwrap().to_pattern().unwrap()
+    }
+
+    #[test]
+    fn canonical_forms() {
+        assert_eq!("abc", reprint(r"\Qabc\E"));
+        assert_eq!(r"\.\*\\", reprint(r"\Q.*\\E"));
+        assert_eq!("a|b", reprint("[ab]"));
+        assert_eq!("x[ab]", reprint("x[ab]"));
+        assert_eq!("(:<n>a|b*?)c", reprint("(:<n>a|b*?)c"));
+        assert_eq!(r"[^]a-c[:alpha:]-]\W$", reprint(r"[^]a-c[:alpha:]-]\W$"));
+        assert_eq!("[a]+", reprint("[a]+"));
+        assert_eq!("[a-z_]", reprint("[a-z_]"));
+        assert_eq!("", reprint(""));
+    }
+
+    #[test]
+    fn registered_classes() {
+        let mut config = stream::ParserConfig::new();
+        config.register_class("vowel", &[('a', 'a'), ('e', 'e')]).unwrap();
+        let parser = TokenParser::from_string_with("x[[:vowel:]y]", &config).unwrap();
+        assert_eq!("x[[:vowel:]y]", parser.to_pattern().unwrap());
+    }
+
+    #[test]
+    fn built_streams() {
+        token_use!();
+        let toks = vec![Grouping(Begin(GBegin::Pat)),
+            Grouping(Begin(GBegin::Maybe)), Grouping(Begin(GBegin::Cap { name: None })),
+            Literal(Char('(')), Grouping(End(GEnd::Cap)), Grouping(End(GEnd::Maybe)),
+            Grouping(End(GEnd::Pat))];
+        assert_eq!(r"(\()?", to_pattern(&toks).unwrap());
+    }
+
+    #[test]
+    fn operators() {
+        let mut config = stream::ParserConfig::new();
+        config.operators(true);
+        for pat in &["~(ab|c)*d", "a&b*|c", r"(a&~(b))\&"] {
+            let parser = TokenParser::from_string_with(pat, &config).unwrap();
+            assert_eq!(*pat, parser.to_pattern().unwrap());
+        }
+        assert_eq!(r"a\&\~", reprint("a&~"));
+    }
+
+    #[test]
+    fn colon_starting_a_group() {
+        let group = Pattern::lit(":a").group();
+        let printed = group.to_pattern().unwrap();
+        assert_eq!(r"(\:a)", printed);
+        assert_eq!(&group.to_tokens()[..], TokenParser::from_string(&printed).unwrap().tokens());
+    }
+
+    #[test]
+    fn unprintable_streams() {
+        // A bare range, and a `Not` without an `Or`
+        assert!(TokenParser::from_like("a_", None).unwrap().to_pattern().is_err());
+        assert!(TokenParser::from_glob("a*").unwrap().to_pattern().is_err());
+        // `Maybe` around a sequence
+        token_use!();
+        let sequence = vec![Grouping(Begin(GBegin::Pat)), Grouping(Begin(GBegin::Maybe)),
+            Literal(Char('a')), Literal(Char('b')), Grouping(End(GEnd::Maybe)),
+            Grouping(End(GEnd::Pat))];
+        assert!(to_pattern(&sequence).is_err());
+
+        let unbalanced = vec![Grouping(Begin(GBegin::Pat)), Grouping(Begin(GBegin::Any))];
+        assert!(to_pattern(&unbalanced).is_err());
+    }
+
+    /// Patterns made with the builder, around colons and other characters
+    /// that are special in some places
+    fn arb_pattern() -> impl Strategy<Value = Pattern> {
+        let leaf = "[ab:(|<]{0,3}".prop_map(|text| Pattern::lit(&text));
+        leaf.prop_recursive(4, 24, 2, |inner| prop_oneof![
+            (inner.clone(), inner.clone()).prop_map(|(a, b)| a.then(b)),
+            (inner.clone(), inner.clone()).prop_map(|(a, b)| a.or(b)),
+            inner.clone().prop_map(Pattern::group),
+            inner.clone().prop_map(|pattern| pattern.capture("n")),
+            inner.prop_map(Pattern::zero_or_more),
+        ])
+    }
+
+    proptest! {
+        #[test]
+        fn built_patterns_round_trip(pattern in arb_pattern()) {
+            // An `or` in a sequence can't be written without a group
+            if let Ok(printed) = pattern.to_pattern() {
+                let reparsed = TokenParser::from_string(&printed);
+                prop_assert_eq!(Ok(&pattern.to_tokens()[..]), reparsed.as_ref().map(|parser| parser.tokens()));
+            }
+        }
+
+        #[test]
+        fn printed_patterns_round_trip(pat in r"[ab.^$|()*+?\[\]\-:<>\\dW]{0,14}") {
+            if let Ok(parser) = TokenParser::from_string(&pat) {
+                let printed = parser.to_pattern().unwrap();
+                let reparsed = TokenParser::from_string(&printed).unwrap();
+                prop_assert_eq!(parser.tokens(), reparsed.tokens());
+            }
+        }
+    }
+}
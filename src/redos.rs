@@ -0,0 +1,625 @@
+//! Finds patterns that backtracking matchers can take exponential or
+//! polynomial time on.
+//!
+//! The `Graph` of a pattern is turned into an automaton without empty moves,
+//! where a move counts twice if the graph has two ways of making it. Then:
+//!
+//! * If some state can get back to itself reading a string `w` in two
+//!   different ways, each extra `w` doubles the ways of reading the text, as
+//!   in `(a+)+`, `(a|a)*` and `(\w|\d)*x`. That is exponential.
+//! * If a state `p` can read `w` looping on itself, moving to `q` and `q`
+//!   can loop on `w` too, the text can be split between them in a number of
+//!   ways that grows with its length, as in `a*a*`. Chains of `k` such
+//!   states give degree `k`.
+//!
+//! `^` and `$` are assumed to always hold, which can only find more. The
+//! search for a match at every position of the text isn't counted, so `a*`
+//! alone is fine even though searching with it can be quadratic.
+//!
+//! The searches share a budget of work, so that screening a pattern takes
+//! time linear in the budget whatever the pattern. If it runs out, the
+//! analysis gives up and says so.
+//!
+//! ```
+//! use toy_regex::graph::Options;
+//! use toy_regex::redos::{analyze, Complexity};
+//! use toy_regex::stream::TokenParser;
+//!
+//! let parser = TokenParser::from_string("^x(a|ab|b)*$").unwrap();
+//! let findings = analyze(&parser, &Options::new()).unwrap().findings;
+//! assert_eq!(Complexity::Exponential, findings[0].complexity);
+//! assert_eq!(2..11, findings[0].span);
+//! assert_eq!("xaabaabaabc", findings[0].attack.text(3));
+//! ```
+
+use std::cell::{Cell, OnceCell};
+use std::cmp::{max, min};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+
+use ast::Ast;
+use charset::CharSet;
+use dfa::{pick, Dfa};
+use graph::{Graph, Options, State};
+use scc;
+use stream::{GBegin, Group, Span, Token, TokenParser};
+
+/// How the time a backtracking matcher can take grows with the length of
+/// the text
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Complexity {
+    /// The degree, at least 2
+    Polynomial(u32),
+    Exponential,
+}
+
+/// A family of slow texts: `prefix`, then `pump` as many times as wanted,
+/// then `suffix` to make the match fail so that every way is tried
+#[derive(Clone, PartialEq, Debug)]
+pub struct Attack {
+    pub prefix: String,
+    pub pump: String,
+    /// Empty if every text starting with `prefix` and the pumps matches
+    pub suffix: String,
+}
+
+impl Attack {
+    /// The text with `pump` repeated `repeats` times
+    pub fn text(&self, repeats: usize) -> String {
+        format!("{}{}{}", self.prefix, self.pump.repeat(repeats), self.suffix)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Finding {
+    /// The quantifier at fault, or the quantifiers if there are several
+    pub span: Span,
+    pub complexity: Complexity,
+    pub attack: Attack,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Analysis {
+    /// All exponential findings, one per span, or else the polynomial one
+    /// of the highest degree, if any
+    pub findings: Vec<Finding>,
+    /// The budget ran out, so there may be more findings than these, or
+    /// worse ones
+    pub gave_up: bool,
+}
+
+/// Above this many states, looking for a suffix is given up
+const DFA_LIMIT: usize = 10_000;
+/// The work `analyze` does before giving up, counted in states visited
+pub const WORK_LIMIT: usize = 1_000_000;
+
+/// Looks for ambiguity in `parser`'s pattern compiled with `options`, with
+/// a budget of `WORK_LIMIT`
+pub fn analyze(parser: &TokenParser, options: &Options) -> Result<Analysis, String> {
+    analyze_limited(parser, options, WORK_LIMIT)
+}
+
+/// Looks for ambiguity in `parser`'s pattern compiled with `options`, giving
+/// up after visiting `limit` states in all
+pub fn analyze_limited(parser: &TokenParser, options: &Options, limit: usize)
+    -> Result<Analysis, String> {
+    let (graph, quantifiers) = Graph::compile_quantifiers(&Ast::from_tokens(parser.tokens())?,
+        options, false)?;
+    let analyzer = Analyzer::new(&graph, parser, quantifiers, limit);
+
+    let mut findings = analyzer.exponential();
+    if findings.is_empty() {
+        findings.extend(analyzer.polynomial());
+    }
+    Ok(Analysis { findings, gave_up: analyzer.budget.get().is_none() })
+}
+
+fn empty_moves(state: &State) -> Vec<usize> {
+    match *state {
+        State::Split(a, b) if a == b => vec![a],
+        State::Split(a, b) => vec![a, b],
+        State::Assert(_, next) => vec![next],
+        State::Char(..) | State::Match => Vec::new(),
+    }
+}
+
+/// Adds `count` ways of reaching `target`, counting up to 2
+fn add_ways(ways: &mut Vec<(usize, u8)>, target: usize, count: u8) {
+    match ways.iter_mut().find(|&&mut (t, _)| t == target) {
+        Some(&mut (_, ref mut ways)) => *ways = min(2, *ways + count),
+        None => ways.push((target, count)),
+    }
+}
+
+/// A step of the same text read along several paths: the states each path
+/// moves from and to, and the character read
+type Step = (Vec<(usize, usize)>, char);
+
+/// The states of the paths, and whether they're different yet
+type Paths = (Vec<usize>, bool);
+
+struct Analyzer<'a> {
+    graph: &'a Graph,
+    parser: &'a TokenParser,
+    quantifiers: Vec<Range<usize>>,
+    /// For every state, the `Char` states its empty moves lead to, with the
+    /// number of ways
+    ways: Vec<Vec<(usize, u8)>>,
+    /// The `Char` states, which are the states of the automaton without
+    /// empty moves
+    chars: Vec<usize>,
+    /// The work left, or `None` once it has run out
+    budget: Cell<Option<usize>>,
+    /// The anchored automaton suffixes are found with, built once
+    dfa: OnceCell<Option<Dfa>>,
+}
+
+impl<'a> Analyzer<'a> {
+    fn new(graph: &'a Graph, parser: &'a TokenParser, quantifiers: Vec<Range<usize>>,
+           limit: usize) -> Analyzer<'a> {
+        let states = graph.states();
+        let moves: Vec<Vec<usize>> = states.iter().map(empty_moves).collect();
+        let components = scc::components(&moves);
+        let mut component_of = vec![0; states.len()];
+        for (i, component) in components.iter().enumerate() {
+            for &state in component {
+                component_of[state] = i;
+            }
+        }
+
+        // Components come sinks first, so those a component leads to are done
+        let mut component_ways: Vec<Vec<(usize, u8)>> = Vec::with_capacity(components.len());
+        for (i, component) in components.iter().enumerate() {
+            let mut ways = Vec::new();
+            let mut cyclic = component.len() > 1;
+            for &state in component {
+                if let State::Char(..) = states[state] {
+                    add_ways(&mut ways, state, 1);
+                }
+                for next in empty_moves(&states[state]) {
+                    if component_of[next] == i {
+                        cyclic = true;
+                        continue;
+                    }
+                    for &(target, count) in &component_ways[component_of[next]] {
+                        add_ways(&mut ways, target, count);
+                    }
+                }
+            }
+            // Going round the cycle any number of times first
+            if cyclic {
+                for way in &mut ways {
+                    way.1 = 2;
+                }
+            }
+            component_ways.push(ways);
+        }
+
+        let ways = component_of.iter().map(|&i| component_ways[i].clone()).collect();
+        let chars = (0..states.len())
+            .filter(|&state| matches!(states[state], State::Char(..)))
+            .collect();
+        Analyzer {
+            graph,
+            parser,
+            quantifiers,
+            ways,
+            chars,
+            budget: Cell::new(Some(limit)),
+            dfa: OnceCell::new(),
+        }
+    }
+
+    /// Takes `work` out of the budget, or says it has run out
+    fn spend(&self, work: usize) -> bool {
+        let left = self.budget.get().and_then(|left| left.checked_sub(work));
+        self.budget.set(left);
+        left.is_some()
+    }
+
+    fn set(&self, state: usize) -> &CharSet {
+        match self.graph.states()[state] {
+            State::Char(ref set, _) => set,
+            _ => unreachable!("Only Char states read characters"),
+        }
+    }
+
+    /// Where reading a character in `state` can lead, with the number of
+    /// ways
+    fn moves(&self, state: usize) -> &[(usize, u8)] {
+        match self.graph.states()[state] {
+            State::Char(_, next) => &self.ways[next],
+            _ => unreachable!("Only Char states read characters"),
+        }
+    }
+
+    /// The states the empty moves between reading in `from` and reading in
+    /// `to` go through
+    fn between(&self, from: usize, to: usize) -> Vec<usize> {
+        let next = match self.graph.states()[from] {
+            State::Char(_, next) => next,
+            _ => unreachable!("Only Char states read characters"),
+        };
+        let mut seen = vec![false; self.graph.states().len()];
+        let mut stack = vec![next];
+        let mut through = Vec::new();
+        while let Some(state) = stack.pop() {
+            if seen[state] || !self.ways[state].iter().any(|&(target, _)| target == to) {
+                continue;
+            }
+            seen[state] = true;
+            through.push(state);
+            stack.extend(empty_moves(&self.graph.states()[state]));
+        }
+        through
+    }
+
+    /// A shortest text leading from the start to reading in `target`
+    fn prefix(&self, target: usize) -> Option<String> {
+        let mut parents: HashMap<usize, Option<(usize, char)>> = HashMap::new();
+        let mut queue = VecDeque::new();
+        for &(state, _) in &self.ways[self.graph.start()] {
+            parents.insert(state, None);
+            queue.push_back(state);
+        }
+
+        while let Some(state) = queue.pop_front() {
+            if !self.spend(1) {
+                return None;
+            }
+            if state == target {
+                let mut text = Vec::new();
+                let mut at = state;
+                while let Some((parent, c)) = parents[&at] {
+                    text.push(c);
+                    at = parent;
+                }
+                return Some(text.into_iter().rev().collect());
+            }
+            let c = match self.set(state).ranges().first() {
+                Some(&(begin, end)) => pick(begin, end).1,
+                None => continue,
+            };
+            for &(next, _) in self.moves(state) {
+                if let Entry::Vacant(entry) = parents.entry(next) {
+                    entry.insert(Some((state, c)));
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// A shortest text after which reading `prefix` and many `pump`s doesn't
+    /// match the whole text
+    fn suffix(&self, prefix: &str, pump: &str) -> String {
+        let dfa = self.dfa.get_or_init(|| Dfa::anchored_limited(self.graph, DFA_LIMIT).ok());
+        let dfa = match *dfa {
+            Some(ref dfa) => dfa,
+            None => return String::new(),
+        };
+        let mut state = dfa.run(0, prefix);
+        // Enough pumps to be in the cycle they end up in
+        for _ in 0..dfa.state_count() {
+            state = dfa.run(state, pump);
+        }
+        dfa.shortest_from(state, false).unwrap_or_default()
+    }
+
+    /// Searches for a text read from every `from` state to the matching `to`
+    /// state, all at once. `fork` says whether the paths are different yet,
+    /// given whether they were and the step taken.
+    fn search<F>(&self, from: Vec<usize>, to: Vec<usize>, fork: F) -> Option<Vec<Step>>
+        where F: Fn(bool, &[(usize, usize, u8)]) -> bool {
+        let start = (from, false);
+        let mut parents: HashMap<Paths, Option<(Paths, Step)>> = HashMap::new();
+        parents.insert(start.clone(), None);
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+
+        while let Some((states, forked)) = queue.pop_front() {
+            if !self.spend(1) {
+                return None;
+            }
+            let common = states.iter().skip(1)
+                .fold(self.set(states[0]).clone(), |set, &state| set.intersect(self.set(state)));
+            let c = match common.ranges().first() {
+                Some(&(begin, end)) => pick(begin, end).1,
+                None => continue,
+            };
+
+            for moves in self.combinations(&states)? {
+                let next_forked = fork(forked, &moves);
+                let next: Vec<usize> = moves.iter().map(|&(_, to, _)| to).collect();
+                let key = (next.clone(), next_forked);
+                if parents.contains_key(&key) {
+                    continue;
+                }
+                let step = (moves.iter().map(|&(from, to, _)| (from, to)).collect(), c);
+                parents.insert(key.clone(), Some(((states.clone(), forked), step)));
+
+                if next_forked && next == to {
+                    let mut steps = Vec::new();
+                    let mut at = key;
+                    while let Some((parent, step)) = parents[&at].clone() {
+                        steps.push(step);
+                        at = parent;
+                    }
+                    steps.reverse();
+                    return Some(steps);
+                }
+                queue.push_back(key);
+            }
+        }
+        None
+    }
+
+    /// Every way of making one move from each of `states`, or `None` if the
+    /// budget runs out making them
+    fn combinations(&self, states: &[usize]) -> Option<Vec<Vec<(usize, usize, u8)>>> {
+        let mut combinations = vec![Vec::new()];
+        for &state in states {
+            let mut longer = Vec::new();
+            for combination in &combinations {
+                if !self.spend(self.moves(state).len()) {
+                    return None;
+                }
+                for &(next, ways) in self.moves(state) {
+                    let mut combination: Vec<(usize, usize, u8)> = combination.clone();
+                    combination.push((state, next, ways));
+                    longer.push(combination);
+                }
+            }
+            combinations = longer;
+        }
+        Some(combinations)
+    }
+
+    /// The states that the paths of `steps` read in or go through
+    fn involved(&self, steps: &[Step]) -> Vec<usize> {
+        let mut involved = Vec::new();
+        for (moves, _) in steps {
+            for &(from, to) in moves {
+                involved.push(from);
+                involved.extend(self.between(from, to));
+            }
+        }
+        involved
+    }
+
+    fn quantifier_span(&self, quantifier: usize) -> Span {
+        let index = self.parser.tokens().iter().enumerate()
+            .filter(|&(_, tok)| match *tok {
+                Token::Grouping(Group::Begin(ref begin)) => {
+                    matches!(*begin, GBegin::Maybe | GBegin::Always | GBegin::Any)
+                },
+                _ => false,
+            })
+            .nth(quantifier)
+            .map(|(index, _)| index)
+            .expect("One Begin token per quantifier");
+        self.parser.spans()[index].clone()
+    }
+
+    /// The smallest quantifier containing every state, or else the span of
+    /// the smallest quantifiers that contain some
+    fn span(&self, states: &[usize]) -> Span {
+        let smallest = |keep: &dyn Fn(&Range<usize>) -> bool| {
+            (0..self.quantifiers.len())
+                .filter(|&q| keep(&self.quantifiers[q]))
+                .min_by_key(|&q| self.quantifiers[q].len())
+        };
+
+        if let Some(q) = smallest(&|range| states.iter().all(|state| range.contains(state))) {
+            return self.quantifier_span(q);
+        }
+        let spans: Vec<Span> = states.iter()
+            .filter_map(|&state| smallest(&|range| range.contains(&state)))
+            .map(|q| self.quantifier_span(q))
+            .collect();
+        match (spans.iter().map(|span| span.start).min(), spans.iter().map(|span| span.end).max()) {
+            (Some(start), Some(end)) => start..end,
+            _ => self.parser.spans()[0].clone(),
+        }
+    }
+
+    /// The finding for `steps` read from `start`, unless the budget runs out
+    /// finding a prefix
+    fn finding(&self, start: usize, steps: &[Step], complexity: Complexity) -> Option<Finding> {
+        let prefix = self.prefix(start)?;
+        let pump: String = steps.iter().map(|&(_, c)| c).collect();
+        let suffix = self.suffix(&prefix, &pump);
+        Some(Finding {
+            span: self.span(&self.involved(steps)),
+            complexity,
+            attack: Attack { prefix, pump, suffix },
+        })
+    }
+
+    /// States that can read some text back to themselves along two
+    /// different paths, keeping the shortest text for each span
+    fn exponential(&self) -> Vec<Finding> {
+        let mut findings: Vec<Finding> = Vec::new();
+        for &state in &self.chars {
+            if self.prefix(state).is_none() {
+                continue;
+            }
+            // The paths fork by moving to different states, or by taking one
+            // move two ways
+            let fork = |forked: bool, moves: &[(usize, usize, u8)]| {
+                forked || moves[0].1 != moves[1].1 || (moves[0] == moves[1] && moves[0].2 > 1)
+            };
+            let finding = self.search(vec![state, state], vec![state, state], fork)
+                .and_then(|steps| self.finding(state, &steps, Complexity::Exponential));
+            if let Some(finding) = finding {
+                match findings.iter_mut().find(|found| found.span == finding.span) {
+                    Some(found) => if finding.attack.pump.len() < found.attack.pump.len() {
+                        *found = finding;
+                    },
+                    None => findings.push(finding),
+                }
+            }
+        }
+        findings.sort_by_key(|finding| (finding.span.start, finding.span.end));
+        findings
+    }
+
+    /// The longest chain of states that each loop on some text and also
+    /// move on it to the next one, which loops on it too
+    fn polynomial(&self) -> Option<Finding> {
+        let looping: Vec<usize> = self.chars.iter().cloned()
+            .filter(|&state| self.prefix(state).is_some()
+                && self.search(vec![state], vec![state], |_, _| true).is_some())
+            .collect();
+
+        let mut links: HashMap<usize, Vec<(usize, Vec<Step>)>> = HashMap::new();
+        for &p in &looping {
+            for &q in looping.iter().filter(|&&q| q != p) {
+                if let Some(steps) = self.search(vec![p, p, q], vec![p, q, q], |_, _| true) {
+                    links.entry(p).or_default().push((q, steps));
+                }
+            }
+        }
+
+        // The longest chain from each state. Without exponential ambiguity
+        // there are no cycles, but guard against them anyway.
+        let mut longest: HashMap<usize, u32> = HashMap::new();
+        fn chain(state: usize, links: &HashMap<usize, Vec<(usize, Vec<Step>)>>,
+                 longest: &mut HashMap<usize, u32>, depth: usize) -> u32 {
+            if let Some(&length) = longest.get(&state) {
+                return length;
+            }
+            let mut length = 1;
+            if depth < links.len() + 1 {
+                for &(next, _) in links.get(&state).map(Vec::as_slice).unwrap_or(&[]) {
+                    length = max(length, 1 + chain(next, links, longest, depth + 1));
+                }
+            }
+            longest.insert(state, length);
+            length
+        }
+
+        let (start, degree) = looping.iter()
+            .map(|&state| (state, chain(state, &links, &mut longest, 0)))
+            .max_by_key(|&(state, degree)| (degree, ::std::cmp::Reverse(state)))?;
+        if degree < 2 {
+            return None;
+        }
+
+        // Every link of the chain, for the span
+        let mut steps = Vec::new();
+        let mut at = start;
+        let mut first = None;
+        while let Some(&(next, ref link)) = links.get(&at)
+            .and_then(|links| links.iter().find(|&&(next, _)| longest[&next] + 1 == longest[&at])) {
+            first.get_or_insert_with(|| link.clone());
+            steps.extend(link.iter().cloned());
+            at = next;
+        }
+
+        let mut finding = self.finding(start, &first.unwrap(), Complexity::Polynomial(degree))?;
+        finding.span = self.span(&self.involved(&steps));
+        Some(finding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use dfa::Dfa;
+    use graph::{Graph, Options};
+    use redos::{analyze, analyze_limited, Complexity, Finding};
+    use stream::TokenParser;
+
+    fn findings(pat: &str) -> Vec<Finding> {
+        let analysis = analyze(&TokenParser::from_string(pat).unwrap(), &Options::new()).unwrap();
+        assert!(!analysis.gave_up, "{}", pat);
+        analysis.findings
+    }
+
+    fn complexity(pat: &str) -> Vec<(Complexity, ::std::ops::Range<usize>)> {
+        findings(pat).into_iter().map(|finding| (finding.complexity, finding.span)).collect()
+    }
+
+    #[test]
+    fn exponential() {
+        assert_eq!(vec![(Complexity::Exponential, 0..5)], complexity("(a+)+"));
+        assert_eq!(vec![(Complexity::Exponential, 0..6)], complexity("(a|a)*"));
+        assert_eq!(vec![(Complexity::Exponential, 0..8)], complexity(r"(\w|\d)*x"));
+        assert_eq!(vec![(Complexity::Exponential, 1..6)], complexity("x(a*)*y"));
+        assert_eq!(vec![(Complexity::Exponential, 1..7), (Complexity::Exponential, 8..14)],
+            complexity("^(a|a)+-(b|b)+$"));
+    }
+
+    #[test]
+    fn polynomial() {
+        assert_eq!(vec![(Complexity::Polynomial(2), 0..4)], complexity("a*a*"));
+        assert_eq!(vec![(Complexity::Polynomial(3), 1..10)], complexity(r"^\d+\d+\d+$"));
+        assert_eq!(vec![(Complexity::Polynomial(2), 0..8)], complexity(r"\s*x?\s*;"));
+    }
+
+    #[test]
+    fn safe() {
+        for pat in &["a*b*", "^[a-z]+$", "(ab)*", "a|b|ab", "(a|b)*c", r"^\d+-\d+$", ""] {
+            assert_eq!(Vec::<Finding>::new(), findings(pat), "{}", pat);
+        }
+    }
+
+    #[test]
+    fn attacks() {
+        let finding = &findings("^(a+)+$")[0];
+        assert_eq!("", finding.attack.prefix);
+        assert_eq!("a", finding.attack.pump);
+        assert_eq!("aaab", finding.attack.text(3));
+
+        let finding = &findings(r"^x(\w|\d)*y$")[0];
+        assert_eq!(("x", "00", ""), (&finding.attack.prefix[..], &finding.attack.pump[..],
+            &finding.attack.suffix[..]));
+
+        let finding = &findings("^a*a*$")[0];
+        assert_eq!("aab", finding.attack.text(2));
+    }
+
+    #[test]
+    fn options_matter() {
+        let parser = TokenParser::from_string("(a|A)*").unwrap();
+        assert!(analyze(&parser, &Options::new()).unwrap().findings.is_empty());
+        let mut options = Options::new();
+        options.case_insensitive(true);
+        assert_eq!(Complexity::Exponential,
+            analyze(&parser, &options).unwrap().findings[0].complexity);
+    }
+
+    #[test]
+    fn budget() {
+        let parser = TokenParser::from_string(r"^\d*\d*\d*\d*x$").unwrap();
+        let analysis = analyze_limited(&parser, &Options::new(), 50).unwrap();
+        assert!(analysis.gave_up);
+        let analysis = analyze(&parser, &Options::new()).unwrap();
+        assert!(!analysis.gave_up);
+        assert_eq!(Complexity::Polynomial(4), analysis.findings[0].complexity);
+    }
+
+    proptest! {
+        #[test]
+        fn attacks_fit_the_pattern(pat in r"[ab|()*+?]{0,10}") {
+            if let Ok(parser) = TokenParser::from_string(&pat) {
+                let dfa = Dfa::anchored(&Graph::from_string(&pat).unwrap()).unwrap();
+                for finding in analyze(&parser, &Options::new()).unwrap().findings {
+                    prop_assert!(finding.span.end <= pat.len(), "{:?} in {}", finding, pat);
+                    // The pumps can always be followed by a match or the suffix
+                    let attack = &finding.attack;
+                    let pumped = format!("{}{}", attack.prefix, attack.pump.repeat(3));
+                    let mut state = dfa.run(0, &pumped);
+                    let live = dfa.live();
+                    prop_assert!(live[state], "{:?} in {}", finding, pat);
+                    state = dfa.run(state, &attack.suffix);
+                    prop_assert!(!dfa.is_accepting(state) || attack.suffix.is_empty(),
+                        "{:?} in {}", finding, pat);
+                }
+            }
+        }
+    }
+}
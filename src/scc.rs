@@ -0,0 +1,67 @@
+//! Strongly connected components, for the analyses of automata.
+
+use std::cmp::min;
+
+/// Tarjan's algorithm, with `next[state]` the states `state` leads to.
+/// Components come sinks first: each only leads to itself and to earlier
+/// ones.
+pub fn components(next: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut components = Components {
+        next,
+        index: vec![None; next.len()],
+        low: vec![0; next.len()],
+        stack: Vec::new(),
+        on_stack: vec![false; next.len()],
+        found: Vec::new(),
+    };
+    for state in 0..next.len() {
+        if components.index[state].is_none() {
+            components.visit(state);
+        }
+    }
+    components.found
+}
+
+struct Components<'a> {
+    next: &'a [Vec<usize>],
+    index: Vec<Option<usize>>,
+    low: Vec<usize>,
+    stack: Vec<usize>,
+    on_stack: Vec<bool>,
+    found: Vec<Vec<usize>>,
+}
+
+impl<'a> Components<'a> {
+    fn visit(&mut self, state: usize) {
+        let index = self.stack.len() + self.found.iter().map(Vec::len).sum::<usize>();
+        self.index[state] = Some(index);
+        self.low[state] = index;
+        self.stack.push(state);
+        self.on_stack[state] = true;
+
+        for &next in &self.next[state] {
+            match self.index[next] {
+                None => {
+                    self.visit(next);
+                    self.low[state] = min(self.low[state], self.low[next]);
+                },
+                Some(next_index) if self.on_stack[next] => {
+                    self.low[state] = min(self.low[state], next_index);
+                },
+                Some(_) => (),
+            }
+        }
+
+        if Some(self.low[state]) == self.index[state] {
+            let mut component = Vec::new();
+            while let Some(member) = self.stack.pop() {
+                self.on_stack[member] = false;
+                component.push(member);
+                if member == state {
+                    break;
+                }
+            }
+            self.found.push(component);
+        }
+    }
+}
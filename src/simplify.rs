@@ -0,0 +1,303 @@
+//! Rewrites patterns into cheaper ones that match the same strings.
+//!
+//! * Nested quantifiers merge: `a**` is `a*`, `a+?` is `a*`. Through a
+//!   capture group only when the outer one adds nothing, like `(a*)*`.
+//! * `Or` groups with a single branch go away, so `[a][b]` is `ab`. Inside a
+//!   `Not` they stay, to keep the bracket expression.
+//! * `Not(Not(x))` is `x`, and so is `Complement(Complement(x))`.
+//! * Repeated branches are dropped, so `a|a` is `a`, and so are repeated
+//!   operands of an `Intersection`.
+//! * Adjacent branches with a common prefix share it: `foo|foobar` becomes
+//!   `foo` followed by an optional `bar`.
+//!
+//! Capture groups are never added, removed or merged. Rules that would do so,
+//! like dropping a repeated `(a)` branch, are skipped.
+//!
+//! A sequence under a quantifier that isn't in a capture group is put in a
+//! single branch `Or`, which groups without capturing.
+
+use ast::{self, Ast, Repetition};
+use stream::Token;
+
+impl Ast {
+    /// The simplified pattern (see the `simplify` module)
+    pub fn simplify(self) -> Ast {
+        simplify(self)
+    }
+}
+
+/// Simplifies a well-formed token stream (see the module documentation)
+pub fn simplify_tokens(toks: &[Token]) -> Result<Vec<Token>, String> {
+    Ok(Ast::from_tokens(toks)?.simplify().to_tokens())
+}
+
+fn has_captures(ast: &Ast) -> bool {
+    match *ast {
+        Ast::Group(..) => true,
+        Ast::Concat(ref items) | Ast::Alternation(ref items) | Ast::Intersection(ref items) => {
+            items.iter().any(has_captures)
+        },
+        Ast::Repetition(_, ref inner) | Ast::Not(ref inner) | Ast::Complement(ref inner) => {
+            has_captures(inner)
+        },
+        _ => false,
+    }
+}
+
+/// The one quantifier that means the same as `outer` applied over `inner`
+fn combine(outer: Repetition, inner: Repetition) -> Repetition {
+    match (outer, inner) {
+        (Repetition::Maybe, Repetition::Maybe) => Repetition::Maybe,
+        (Repetition::Always, Repetition::Always) => Repetition::Always,
+        _ => Repetition::Any,
+    }
+}
+
+/// The items of a sequence, or the item itself
+fn items(ast: &Ast) -> &[Ast] {
+    match *ast {
+        Ast::Concat(ref items) => items,
+        ref ast => ::std::slice::from_ref(ast),
+    }
+}
+
+fn is_empty(ast: &Ast) -> bool {
+    items(ast).is_empty()
+}
+
+fn simplify(ast: Ast) -> Ast {
+    match ast {
+        Ast::Concat(items) => {
+            let mut flat = Vec::with_capacity(items.len());
+            for item in items {
+                match simplify(item) {
+                    Ast::Concat(more) => flat.extend(more),
+                    item => flat.push(item),
+                }
+            }
+            ast::concat(flat)
+        },
+        Ast::Alternation(branches) => alternation(branches.into_iter().map(simplify).collect()),
+        Ast::Repetition(kind, inner) => repetition(kind, simplify(*inner)),
+        Ast::Group(name, inner) => Ast::Group(name, Box::new(simplify(*inner))),
+        Ast::Not(inner) => match *inner {
+            Ast::Alternation(branches) => {
+                let branches = dedup(branches.into_iter().map(simplify).collect());
+                Ast::Not(Box::new(Ast::Alternation(branches)))
+            },
+            inner => match simplify(inner) {
+                Ast::Not(twice) => *twice,
+                inner => Ast::Not(Box::new(inner)),
+            },
+        },
+        Ast::Complement(inner) => match simplify(*inner) {
+            Ast::Complement(twice) => *twice,
+            inner => Ast::Complement(Box::new(inner)),
+        },
+        Ast::Intersection(operands) => {
+            let mut operands = dedup(operands.into_iter().map(simplify).collect());
+            if operands.len() == 1 {
+                return operands.pop().unwrap();
+            }
+            Ast::Intersection(operands)
+        },
+        leaf => leaf,
+    }
+}
+
+/// `kind` over an already simplified `inner`
+fn repetition(kind: Repetition, inner: Ast) -> Ast {
+    match inner {
+        ref empty if is_empty(empty) => inner,
+        Ast::Repetition(inner_kind, operand) => Ast::Repetition(combine(kind, inner_kind), operand),
+        Ast::Group(name, operand) => {
+            match *operand {
+                Ast::Repetition(inner_kind, repeated) if combine(kind, inner_kind) == inner_kind => {
+                    Ast::Group(name, Box::new(Ast::Repetition(inner_kind, repeated)))
+                },
+                operand => Ast::Repetition(kind, Box::new(Ast::Group(name, Box::new(operand)))),
+            }
+        },
+        sequence @ Ast::Concat(_) => {
+            Ast::Repetition(kind, Box::new(Ast::Alternation(vec![sequence])))
+        },
+        inner => Ast::Repetition(kind, Box::new(inner)),
+    }
+}
+
+/// Drops branches equal to an earlier one, unless they capture
+fn dedup(branches: Vec<Ast>) -> Vec<Ast> {
+    let mut kept: Vec<Ast> = Vec::with_capacity(branches.len());
+    for branch in branches {
+        if has_captures(&branch) || !kept.contains(&branch) {
+            kept.push(branch);
+        }
+    }
+    kept
+}
+
+/// An `Or` of already simplified `branches`
+fn alternation(branches: Vec<Ast>) -> Ast {
+    let mut flat = Vec::with_capacity(branches.len());
+    for branch in branches {
+        match branch {
+            Ast::Alternation(more) => flat.extend(more),
+            branch => flat.push(branch),
+        }
+    }
+
+    let mut branches = factor(dedup(flat));
+    if branches.len() == 1 {
+        return branches.pop().unwrap();
+    }
+    Ast::Alternation(branches)
+}
+
+/// The first item of a branch, if a prefix starting with it can be shared
+fn leading(branch: &Ast) -> Option<&Ast> {
+    items(branch).first().filter(|item| !has_captures(item))
+}
+
+/// Pulls the common prefix out of each run of adjacent branches that start
+/// the same way
+fn factor(branches: Vec<Ast>) -> Vec<Ast> {
+    let mut factored = Vec::with_capacity(branches.len());
+    let mut start = 0;
+
+    while start < branches.len() {
+        let first = leading(&branches[start]);
+        let mut end = start + 1;
+        while first.is_some() && end < branches.len() && leading(&branches[end]) == first {
+            end += 1;
+        }
+        if end - start < 2 {
+            factored.push(branches[start].clone());
+            start = end;
+            continue;
+        }
+
+        let run: Vec<&[Ast]> = branches[start..end].iter().map(items).collect();
+        let mut len = 1;
+        while run.iter().all(|seq| len < seq.len() && seq[len] == run[0][len])
+            && !has_captures(&run[0][len]) {
+            len += 1;
+        }
+
+        let suffixes: Vec<Ast> = run.iter().map(|seq| ast::concat(seq[len..].to_vec())).collect();
+        let optional = suffixes.iter().any(is_empty);
+        let rest: Vec<Ast> = suffixes.into_iter().filter(|suffix| !is_empty(suffix)).collect();
+
+        let mut sequence = run[0][..len].to_vec();
+        if !rest.is_empty() {
+            let mut tail = alternation(rest);
+            if optional {
+                tail = repetition(Repetition::Maybe, tail);
+            }
+            match tail {
+                Ast::Concat(more) => sequence.extend(more),
+                tail => sequence.push(tail),
+            }
+        }
+        factored.push(ast::concat(sequence));
+        start = end;
+    }
+
+    factored
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use ast::{Ast, Repetition};
+    use graph::Graph;
+    use simplify::simplify_tokens;
+    use stream::TokenParser;
+    use validate::validate;
+    use visit::captures;
+
+    fn simplified(pat: &str) -> Ast {
+        Ast::from_string(pat).unwrap().simplify()
+    }
+
+    fn same(simple: &str, pat: &str) {
+        assert_eq!(Ast::from_string(simple).unwrap(), simplified(pat), "simplifying {}", pat);
+    }
+
+    #[test]
+    fn nested_quantifiers() {
+        same("a*", "a**");
+        same("a*", "a+?");
+        same("a+", "a++");
+        same("(a*)", "(a*)*");
+        same("(a+)*", "(a+)*");
+        let empty = Ast::Repetition(Repetition::Any, Box::new(Ast::Concat(Vec::new())));
+        assert_eq!(Ast::Concat(Vec::new()), empty.simplify());
+    }
+
+    #[test]
+    fn single_branches_and_negation() {
+        same("ab", "[a][b]");
+        same("[^a]b", "[^a][b]");
+        let double = Ast::Not(Box::new(Ast::Not(Box::new(Ast::Literal('a')))));
+        assert_eq!(Ast::Literal('a'), double.simplify());
+        let double = Ast::Complement(Box::new(Ast::Complement(Box::new(Ast::Literal('a')))));
+        assert_eq!(Ast::Literal('a'), double.simplify());
+        let same = Ast::Intersection(vec![Ast::Literal('a'), Ast::Literal('a')]);
+        assert_eq!(Ast::Literal('a'), same.simplify());
+    }
+
+    #[test]
+    fn repeated_branches() {
+        same("a", "a|a");
+        same("a|b", "a|b|a|[ab]");
+        same("(a)|(a)", "(a)|(a)");
+    }
+
+    #[test]
+    fn common_prefixes() {
+        let foo = Ast::from_string("foo").unwrap();
+        let bar = Ast::from_string("bar").unwrap();
+        let mut expected = match foo {
+            Ast::Concat(items) => items,
+            _ => unreachable!(),
+        };
+        expected.push(Ast::Repetition(Repetition::Maybe, Box::new(Ast::Alternation(vec![bar]))));
+        assert_eq!(Ast::Concat(expected), simplified("foo|foobar"));
+
+        same("a[bc]|d", "ab|ac|d");
+        same("ab?", "a|ab");
+        same("d|ab|c|ae", "d|ab|c|ae");
+        // A shared capture group would become one group
+        same("(a)b|(a)c", "(a)b|(a)c");
+    }
+
+    #[test]
+    fn token_streams() {
+        let toks = TokenParser::from_string("x**|x+?").unwrap().into_tokens();
+        let simple = simplify_tokens(&toks).unwrap();
+        assert_eq!(TokenParser::from_string("x*").unwrap().tokens(), simple);
+    }
+
+    proptest! {
+        #[test]
+        fn same_language_and_captures(pat in r"[ab|()*+?\[\]]{0,12}", texts in prop::collection::vec("[ab]{0,6}", 8)) {
+            if let Ok(parser) = TokenParser::from_string(&pat) {
+                let simple = simplify_tokens(parser.tokens()).unwrap();
+                prop_assert_eq!(Ok(()), validate(&simple));
+                prop_assert_eq!(captures(parser.tokens()), captures(&simple));
+
+                let anchored = |toks: &[::stream::Token]| {
+                    let mut toks = toks.to_vec();
+                    toks.insert(1, ::stream::Token::Literal(::stream::Literal::StartOfLine));
+                    toks.insert(toks.len() - 1, ::stream::Token::Literal(::stream::Literal::EndOfLine));
+                    Graph::from_stream(TokenParser::from_tokens(toks).unwrap()).unwrap()
+                };
+                let (before, after) = (anchored(parser.tokens()), anchored(&simple));
+                for text in &texts {
+                    prop_assert_eq!(before.is_match(text), after.is_match(text), "{} on {}", pat, text);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! SQL `LIKE` and `SIMILAR TO` patterns.
+//!
+//! Both match the whole value, so the tokens are anchored at both ends of
+//! the text, and `%` and `_` match any character including line
+//! terminators, whatever the compile options. The optional escape character
+//! is the one given by the `ESCAPE` clause; in `SIMILAR TO` it also works
+//! inside bracket expressions.
+
+use posix::{interval, repeat};
+use stream::{wrap_span, Cursor, ParserConfig, Span, Spanned};
+
+/// `_`: any character, whatever the compile options say about `AnyChar`
+fn any_char(span: Span) -> Spanned {
+    token_use!();
+    vec![(Literal(Range { begin: '\0', end: ::std::char::MAX }), span)]
+}
+
+/// `%`: any run of characters
+fn any_run(span: Span) -> Spanned {
+    token_use!();
+    wrap_span(GBegin::Any, GEnd::Any, span.clone(), any_char(span))
+}
+
+fn anchored(toks: Spanned, len: usize) -> Spanned {
+    token_use!();
+    let mut body = vec![(Literal(StartOfText), 0..0)];
+    body.extend(toks);
+    body.push((Literal(EndOfText), len..len));
+    wrap_span(GBegin::Pat, GEnd::Pat, 0..len, body)
+}
+
+/// Parses the pattern of `value LIKE pattern [ESCAPE escape]`
+pub fn parse_like(pattern: &str, escape: Option<char>) -> Result<Spanned, String> {
+    token_use!();
+    let mut toks = Vec::new();
+    let mut chars = Cursor::new(pattern);
+
+    loop {
+        let at = chars.pos;
+        let c = match chars.next() {
+            Some(c) => c,
+            None => break,
+        };
+
+        if Some(c) == escape {
+            match chars.next() {
+                Some(next) if next == '%' || next == '_' || Some(next) == escape => {
+                    toks.push((Literal(Char(next)), at..chars.pos));
+                },
+                _ => return Err("Escape character must be followed by %, _ or itself".to_string()),
+            }
+            continue;
+        }
+
+        match c {
+            '%' => toks.extend(any_run(at..chars.pos)),
+            '_' => toks.extend(any_char(at..chars.pos)),
+            _ => toks.push((Literal(Char(c)), at..chars.pos)),
+        }
+    }
+
+    Ok(anchored(toks, pattern.len()))
+}
+
+struct SimilarParser<'a> {
+    chars: Cursor<'a>,
+    escape: Option<char>,
+}
+
+impl<'a> SimilarParser<'a> {
+    fn alternation(&mut self, in_group: bool) -> Result<Spanned, String> {
+        token_use!();
+        let start = self.chars.pos;
+        let mut toks = self.branch(in_group)?;
+        if self.chars.peek() != Some('|') {
+            return Ok(toks);
+        }
+
+        while self.chars.eat("|") {
+            toks.push((Grouping(OrDelimiter), self.chars.pos - 1..self.chars.pos));
+            toks.extend(self.branch(in_group)?);
+        }
+        Ok(wrap_span(GBegin::Or, GEnd::Or, start..self.chars.pos, toks))
+    }
+
+    fn branch(&mut self, in_group: bool) -> Result<Spanned, String> {
+        token_use!();
+        let mut toks = Vec::new();
+
+        loop {
+            match self.chars.peek() {
+                None | Some('|') => return Ok(toks),
+                Some(')') if in_group => return Ok(toks),
+                _ => (),
+            }
+
+            let at = self.chars.pos;
+            let c = self.chars.next().unwrap();
+            let mut atom = if Some(c) == self.escape {
+                let escaped = self.chars.next()
+                    .ok_or("Pattern ended when expecting escaped character".to_string())?;
+                vec![(Literal(Char(escaped)), at..self.chars.pos)]
+            } else {
+                match c {
+                    '%' => any_run(at..self.chars.pos),
+                    '_' => any_char(at..self.chars.pos),
+                    '(' => {
+                        let inner = self.alternation(true)?;
+                        if !self.chars.eat(")") {
+                            return Err("Pattern ended with unclosed groups".to_string());
+                        }
+                        wrap_span(GBegin::Cap { name: None }, GEnd::Cap, at..self.chars.pos, inner)
+                    },
+                    ')' => return Err("Group closed without being opened".to_string()),
+                    '[' => self.chars.bracket_with(&ParserConfig::default(), &['^'], self.escape)?,
+                    '*' | '+' | '?' | '{' =>
+                        return Err(format!("Repetition operator {} has no operand", c)),
+                    _ => vec![(Literal(Char(c)), at..self.chars.pos)],
+                }
+            };
+
+            loop {
+                let (begin, end) = if self.chars.eat("*") {
+                    (GBegin::Any, GEnd::Any)
+                } else if self.chars.eat("+") {
+                    (GBegin::Always, GEnd::Always)
+                } else if self.chars.eat("?") {
+                    (GBegin::Maybe, GEnd::Maybe)
+                } else if self.chars.eat("{") {
+                    let (min, max) = interval(&mut self.chars, "}")?;
+                    atom = repeat(&atom, min, max, at..self.chars.pos)?;
+                    continue;
+                } else {
+                    break;
+                };
+                atom = wrap_span(begin, end, at..self.chars.pos, atom);
+            }
+            toks.extend(atom);
+        }
+    }
+}
+
+/// Parses the pattern of `value SIMILAR TO pattern [ESCAPE escape]`
+pub fn parse_similar_to(pattern: &str, escape: Option<char>) -> Result<Spanned, String> {
+    let mut parser = SimilarParser { chars: Cursor::new(pattern), escape };
+    let toks = parser.alternation(false)?;
+    Ok(anchored(toks, pattern.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::Graph;
+    use stream::TokenParser;
+
+    fn like(pattern: &str, escape: Option<char>) -> Graph {
+        Graph::from_stream(TokenParser::from_like(pattern, escape).unwrap()).unwrap()
+    }
+
+    fn similar(pattern: &str) -> Graph {
+        Graph::from_stream(TokenParser::from_similar_to(pattern, None).unwrap()).unwrap()
+    }
+
+    mod like {
+        use super::like;
+        use stream::TokenParser;
+
+        #[test]
+        fn tokens() {
+            use stream;
+            token_use!();
+            let expected_toks = vec![Grouping(Begin(GBegin::Pat)),
+                Literal(StartOfText), Literal(Char('a')),
+                Grouping(Begin(GBegin::Any)),
+                Literal(Range { begin: '\0', end: ::std::char::MAX }),
+                Grouping(End(GEnd::Any)), Literal(Range { begin: '\0', end: ::std::char::MAX }),
+                Literal(EndOfText), Grouping(End(GEnd::Pat))];
+
+            let parser = stream::TokenParser::from_like("a%_", None).unwrap();
+            assert_eq!(expected_toks, parser.tokens());
+        }
+
+        #[test]
+        fn wildcards() {
+            let pattern = like("a%b_", None);
+            assert!(pattern.is_match("ab!"));
+            assert!(pattern.is_match("a\nxyzb\n"));
+            assert!(!pattern.is_match("ab"));
+            assert!(!pattern.is_match("xab!"));
+            assert!(like("a.c", None).is_match("a.c"));
+            assert!(!like("a.c", None).is_match("abc"));
+        }
+
+        #[test]
+        fn whole_text_in_multiline() {
+            use graph::{Graph, Options};
+            let mut options = Options::new();
+            options.multiline(true);
+            let pattern = Graph::from_stream_with(TokenParser::from_like("abc", None).unwrap(), &options)
+                .unwrap();
+            assert!(pattern.is_match("abc"));
+            assert!(!pattern.is_match("x\nabc\ny"));
+        }
+
+        #[test]
+        fn escape_clause() {
+            let pattern = like("100!%!_!!", Some('!'));
+            assert!(pattern.is_match("100%_!"));
+            assert!(!pattern.is_match("1000_!"));
+            assert!(TokenParser::from_like("a!b", Some('!')).is_err());
+            assert!(TokenParser::from_like("a!", Some('!')).is_err());
+        }
+    }
+
+    mod similar_to {
+        use super::similar;
+        use stream::TokenParser;
+
+        #[test]
+        fn alternation_and_groups() {
+            let pattern = similar("%(b|d)%");
+            assert!(pattern.is_match("abc"));
+            assert!(pattern.is_match("d"));
+            assert!(!pattern.is_match("ace"));
+        }
+
+        #[test]
+        fn repetition() {
+            let pattern = similar("a{2,3}[0-9]+x?");
+            assert!(pattern.is_match("aa1"));
+            assert!(pattern.is_match("aaa12x"));
+            assert!(!pattern.is_match("a1"));
+            assert!(!pattern.is_match("aaaa1"));
+        }
+
+        #[test]
+        fn regex_characters_are_literal() {
+            assert!(similar("a.c$").is_match("a.c$"));
+            assert!(!similar("a.c").is_match("abc"));
+        }
+
+        #[test]
+        fn escape_clause() {
+            let parser = TokenParser::from_similar_to("a#|b#%", Some('#')).unwrap();
+            let pattern = ::graph::Graph::from_stream(parser).unwrap();
+            assert!(pattern.is_match("a|b%"));
+            assert!(!pattern.is_match("a"));
+        }
+
+        #[test]
+        fn escape_in_brackets() {
+            let parser = TokenParser::from_similar_to("[a#]]+[#^#-]", Some('#')).unwrap();
+            let pattern = ::graph::Graph::from_stream(parser).unwrap();
+            assert!(pattern.is_match("a]a^"));
+            assert!(pattern.is_match("]-"));
+            assert!(!pattern.is_match("a#"));
+            assert!(!pattern.is_match("ab"));
+
+            let range = TokenParser::from_similar_to("[!-#]]", Some('#')).unwrap();
+            assert!(::graph::Graph::from_stream(range).unwrap().is_match("A"));
+            assert!(TokenParser::from_similar_to("[a#", Some('#')).is_err());
+        }
+
+        #[test]
+        fn invalid() {
+            assert!(TokenParser::from_similar_to("*a", None).is_err());
+            assert!(TokenParser::from_similar_to("(a", None).is_err());
+            assert!(TokenParser::from_similar_to("a)", None).is_err());
+            assert!(TokenParser::from_similar_to("a{3,1}", None).is_err());
+        }
+
+        #[test]
+        fn nested_intervals() {
+            assert!(TokenParser::from_similar_to("(a{255}){255}", None).is_ok());
+            assert_eq!(Err("Interval expansion too large: more than 100000 tokens".to_string()),
+                TokenParser::from_similar_to("((a{255}){255}){255}", None).map(|_| ()));
+        }
+    }
+}
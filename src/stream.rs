@@ -2,9 +2,18 @@ use std::iter::Peekable;
 use std::str::Chars;
 use std::iter::IntoIterator;
 use std::iter::Inspect;
+use std::collections::BTreeMap;
+use std::ops::Range;
 
 use itertools::Itertools;
 
+use glob;
+use pattern::Pattern;
+use posix;
+use printer;
+use sql;
+use validate::{self, TokenError};
+
 #[macro_export]
 macro_rules! token_use {
     () => {
@@ -16,27 +25,111 @@ macro_rules! token_use {
     }
 }
 
+/// A parsed pattern.
+///
+/// With the `serde` feature it serializes as a map of its source, tokens
+/// and spans, e.g. `a` in JSON is
+///
+/// ```text
+/// {"pattern_source":"a",
+///  "tokens":[{"Grouping":{"Begin":"Pat"}},{"Literal":{"Char":"a"}},{"Grouping":{"End":"Pat"}}],
+///  "spans":[{"start":0,"end":1},{"start":0,"end":1},{"start":0,"end":1}]}
+/// ```
+///
+/// Deserializing checks the tokens the way `from_tokens` does, and that
+/// there is one span per token, each inside the source.
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "RawTokenParser"))]
 pub struct TokenParser {
     pattern_source: String,
-    pub tokens: Vec<Token>,
+    tokens: Vec<Token>,
+    spans: Vec<Span>,
 }
 
-#[derive(PartialEq, Debug)]
+/// A `TokenParser` as it comes off the wire, before it's checked
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct RawTokenParser {
+    pattern_source: String,
+    tokens: Vec<Token>,
+    spans: Vec<Span>,
+}
+
+#[cfg(feature = "serde")]
+impl ::std::convert::TryFrom<RawTokenParser> for TokenParser {
+    type Error = String;
+
+    fn try_from(raw: RawTokenParser) -> Result<TokenParser, String> {
+        if raw.spans.len() != raw.tokens.len() {
+            return Err(format!("{} spans for {} tokens", raw.spans.len(), raw.tokens.len()));
+        }
+        validate::validate(&raw.tokens)?;
+        let source = &raw.pattern_source;
+        for span in &raw.spans {
+            if span.start > span.end || source.get(span.clone()).is_none() {
+                return Err(format!("Span {:?} isn't inside the pattern source", span));
+            }
+        }
+        Ok(TokenParser {
+            pattern_source: raw.pattern_source,
+            tokens: raw.tokens,
+            spans: raw.spans,
+        })
+    }
+}
+
+/// A byte range of a pattern's source
+pub type Span = Range<usize>;
+
+/// Tokens with the span each came from, as the front-ends build them
+pub(crate) type Spanned = Vec<(Token, Span)>;
+
+/// One element of a token stream.
+///
+/// With the `serde` feature, tokens and everything in them use serde's
+/// default, externally tagged, enum representation. Variant and field names
+/// are the Rust ones and are part of the format, so they won't change. In
+/// JSON:
+///
+/// | Token                                  | JSON                                              |
+/// |----------------------------------------|---------------------------------------------------|
+/// | `Literal(Char('a'))`                   | `{"Literal":{"Char":"a"}}`                        |
+/// | `Literal(AnyChar)`                     | `{"Literal":"AnyChar"}`                           |
+/// | `Literal(Range { begin: 'a', end: 'z' })` | `{"Literal":{"Range":{"begin":"a","end":"z"}}}` |
+/// | `Literal(Class { name, ranges })`      | `{"Literal":{"Class":{"name":"v","ranges":[["a","a"]]}}}` |
+/// | `Grouping(Begin(Cap { name: None }))`  | `{"Grouping":{"Begin":{"Cap":{"name":null}}}}`    |
+/// | `Grouping(Begin(Any))`                 | `{"Grouping":{"Begin":"Any"}}`                    |
+/// | `Grouping(End(Cap))`                   | `{"Grouping":{"End":"Cap"}}`                      |
+/// | `Grouping(OrDelimiter)`                | `{"Grouping":"OrDelimiter"}`                      |
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Token {
     Grouping(Group),
     Literal(Literal)
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Literal {
     Char(char),
+    /// `.`: any character but a line terminator, unless the pattern is
+    /// compiled with `graph::Options::dot_all`
     AnyChar,
     // One or both of these could be a collating element?
     // I have no idea how to represent/obtain collation stuff
     Range { begin: char, end: char },
+    /// `^`: the start of the text, or of any line with
+    /// `graph::Options::multiline`
     StartOfLine,
+    /// `$`: the end of the text, or of any line with
+    /// `graph::Options::multiline`
     EndOfLine,
+    /// The start of the text whatever the options, for the front-ends that
+    /// match whole texts. It has no native syntax.
+    StartOfText,
+    /// The end of the text whatever the options
+    EndOfText,
     // Character Classes
     Whitespace,
     Alnum,
@@ -50,15 +143,21 @@ pub enum Literal {
     Punct,
     Upper,
     XDigit,
+    // A class registered through `ParserConfig::register_class`
+    Class { name: String, ranges: Vec<(char, char)> },
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Group {
     Begin(GBegin),
     End(GEnd),
     OrDelimiter,
+    /// Separates the operands of an `And` group
+    AndDelimiter,
 }
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum GBegin {
     Pat,
     Cap{ name : Option<String> }, // Capture group
@@ -67,8 +166,14 @@ pub enum GBegin {
     Always, // +
     Any, // *
     Or,
+    /// `~(...)`: every string its content doesn't match. Unlike `Not`, which
+    /// stands for single characters, it can hold any pattern.
+    Complement,
+    /// `&`: the strings all of its operands match
+    And,
 }
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum GEnd {
     Pat,
     Cap,
@@ -77,13 +182,210 @@ pub enum GEnd {
     Always,
     Any,
     Or,
+    Complement,
+    And,
 }
 
-const ESCAPABLE_CHARS: &'static [char] = &[
-    '.', '+', '?', '*', '(', ')', '[', ']', '|', '^', '$', '\\'
+/// `:` is only special straight after `(`, but is always escaped so that a
+/// literal one can start a capture group
+const ESCAPABLE_CHARS: &[char] = &[
+    '.', '+', '?', '*', '(', ')', '[', ']', '|', '^', '$', '\\', '~', '&', ':'
+];
+
+const POSIX_CLASSES: &[&str] = &[
+    "alnum", "alpha", "blank", "cntrl", "digit", "graph",
+    "lower", "print", "punct", "space", "upper", "xdigit",
 ];
 
+/// Escapes every special character in `text`, so that the result parses to
+/// one `Literal::Char` per character of `text`.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if ESCAPABLE_CHARS.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Prints a token stream in the native syntax, so that parsing the result
+/// with `TokenParser::from_string` gives the same tokens.
+///
+/// Some streams can't be written that way, and printing them fails: the
+/// native syntax has no bare `Range` or POSIX class outside a bracket
+/// expression, no `Not` other than `\W`, `\D`, `\S` and `[^...]`, no text
+/// anchors, and no alternation in the middle of a sequence unless every
+/// branch fits in a bracket expression. Streams from the glob and SQL
+/// front-ends often use these.
+///
+/// `Complement` and `And` groups are printed as `~(...)` and `&`, which only
+/// read back with `ParserConfig::operators`. An `And` has the same limits as
+/// an alternation in the middle of a sequence.
+///
+/// ```
+/// use toy_regex::stream::{to_pattern, TokenParser};
+///
+/// let parser = TokenParser::from_string("[ab]x*").unwrap();
+/// assert_eq!("[ab]x*", to_pattern(parser.tokens()).unwrap());
+/// assert!(TokenParser::from_glob("*.rs").unwrap().to_pattern().is_err());
+/// ```
+pub fn to_pattern(toks: &[Token]) -> Result<String, String> {
+    printer::to_pattern(toks)
+}
+
+/// The syntax a pattern is written in
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Dialect {
+    /// This crate's own syntax
+    #[default]
+    Native,
+    /// POSIX basic regular expressions, as used by `grep` and `sed`
+    Bre,
+    /// POSIX extended regular expressions, as used by `grep -E` and `awk`
+    Ere,
+}
+
+/// Wraps `toks` in a group of the given kind
+pub(crate) fn wrap(begin: GBegin, end: GEnd, toks: Vec<Token>) -> Vec<Token> {
+    let mut wrapped = Vec::with_capacity(toks.len() + 2);
+    wrapped.push(Token::Grouping(Group::Begin(begin)));
+    wrapped.extend(toks);
+    wrapped.push(Token::Grouping(Group::End(end)));
+    wrapped
+}
+
+/// Wraps `toks` in a group of the given kind, whose `Begin` and `End` both
+/// carry `span`, the syntax that produced the group
+pub(crate) fn wrap_span(begin: GBegin, end: GEnd, span: Span, toks: Spanned) -> Spanned {
+    let mut wrapped = Vec::with_capacity(toks.len() + 2);
+    wrapped.push((Token::Grouping(Group::Begin(begin)), span.clone()));
+    wrapped.extend(toks);
+    wrapped.push((Token::Grouping(Group::End(end)), span));
+    wrapped
+}
+
+/// Walks a pattern by byte position, for the front-ends that need more
+/// than one character of lookahead.
+pub(crate) struct Cursor<'a> {
+    text: &'a str,
+    pub pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(text: &'a str) -> Cursor<'a> {
+        Cursor { text, pos: 0 }
+    }
+
+    pub fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    pub fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    pub fn starts_with(&self, prefix: &str) -> bool {
+        self.rest().starts_with(prefix)
+    }
+
+    pub fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// Consumes `prefix` if the rest of the text starts with it
+    pub fn eat(&mut self, prefix: &str) -> bool {
+        if self.starts_with(prefix) {
+            self.pos += prefix.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn number(&mut self) -> Option<u32> {
+        let digits: String = self.rest().chars().take_while(|c| c.is_ascii_digit()).collect();
+        self.pos += digits.len();
+        digits.parse().ok()
+    }
+
+    /// Reads a bracket expression whose `[` was just consumed
+    pub fn bracket(&mut self, config: &ParserConfig) -> Result<Spanned, String> {
+        TokenParser::parse_bracket(self, config, &['^'], None)
+    }
+
+    /// Reads a bracket expression whose `[` was just consumed, negated by
+    /// any of the characters in `negation` instead of just `^`. Inside it
+    /// `escape`, if given, makes the next character literal.
+    pub fn bracket_with(&mut self, config: &ParserConfig, negation: &[char], escape: Option<char>)
+        -> Result<Spanned, String> {
+        TokenParser::parse_bracket(self, config, negation, escape)
+    }
+}
+
+/// Settings that change how `TokenParser` reads a pattern.
+#[derive(PartialEq, Debug, Default)]
+pub struct ParserConfig {
+    classes: BTreeMap<String, Vec<(char, char)>>,
+    dialect: Dialect,
+    operators: bool,
+}
+
+impl ParserConfig {
+    pub fn new() -> ParserConfig {
+        ParserConfig::default()
+    }
+
+    pub fn dialect(&mut self, dialect: Dialect) -> &mut ParserConfig {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Makes `~(...)` (complement) and `&` (intersection) special in the
+    /// native syntax. They are literal characters otherwise, and can always
+    /// be escaped. Only `dfa::Dfa` can match patterns that use them.
+    ///
+    /// `&` binds tighter than `|` and looser than a sequence, so `ab&c|d`
+    /// is `(ab&c)|d`. The parentheses of `~(...)` don't capture.
+    pub fn operators(&mut self, yes: bool) -> &mut ParserConfig {
+        self.operators = yes;
+        self
+    }
+
+    /// Makes `[:name:]` usable inside bracket expressions, matching any
+    /// character in one of the inclusive `ranges`.
+    ///
+    /// Registering a name twice replaces the earlier ranges. The POSIX class
+    /// names can't be overridden.
+    pub fn register_class(&mut self, name: &str, ranges: &[(char, char)])
+        -> Result<(), String> {
+        if name.is_empty() || name.contains(&[':', ']'][..]) {
+            return Err(format!("Invalid character class name \"{}\"", name));
+        }
+        if POSIX_CLASSES.contains(&name) {
+            return Err(format!("Character class {} is built in", name));
+        }
+        if ranges.is_empty() {
+            return Err(format!("Character class {} has no ranges", name));
+        }
+        if let Some(&(begin, end)) = ranges.iter().find(|&&(begin, end)| begin > end) {
+            return Err(format!("Character class {} has backwards range {}-{}", name, begin, end));
+        }
+
+        self.classes.insert(name.to_string(), ranges.to_vec());
+        Ok(())
+    }
+
+    fn class(&self, name: &str) -> Option<&Vec<(char, char)>> {
+        self.classes.get(name)
+    }
+}
+
 impl TokenParser {
+    /// Reads the name of a `(:<name>` capture group, just after its `:`
     fn read_cap_name(chars: &mut Peekable<Chars>)
         -> Result<String, String> {
         chars.next(); // Consume '<'
@@ -101,26 +403,8 @@ impl TokenParser {
         }
     }
 
-    fn prev_object(toks: &[Token]) -> usize {
-        token_use!();
-        let mut groups = 0;
-        for tok in toks.iter().enumerate().rev() {
-            match tok.1 {
-                &Grouping(End(_)) => groups +=1,
-                &Grouping(Begin(_)) => {
-                    groups -= 1;
-                    if groups == 0 {
-                        return tok.0;
-                    }
-                },
-                _ if groups == 0 => return tok.0,
-                _ => continue,
-            }
-        }
-        0
-    }
-
-    fn parse_char_class(chars: &mut Peekable<Chars>) -> Result<Token, String> {
+    fn parse_char_class(chars: &mut Cursor, config: &ParserConfig)
+        -> Result<Token, String> {
         use stream::Token::Literal;
         use stream::Literal::*;
         // Assume that the opening '[' was already consumed
@@ -129,12 +413,10 @@ impl TokenParser {
 
         let mut class = String::new();
 
-        while let Some(&c) = chars.peek() {
-            chars.next();
+        while let Some(c) = chars.next() {
             match c {
                 ':' => {
-                    if Some(&']') == chars.peek() {
-                        chars.next();
+                    if chars.eat("]") {
                         break;
                     } else {
                         class.push(c);
@@ -144,7 +426,7 @@ impl TokenParser {
             }
         }
 
-        if None == chars.peek() {
+        if chars.peek().is_none() {
             return Err("Pattern ended in middle of character class".to_string());
         }
 
@@ -161,251 +443,414 @@ impl TokenParser {
             "space" => Ok(Literal(Whitespace)),
             "upper" => Ok(Literal(Upper)),
             "xdigit" => Ok(Literal(XDigit)),
-            _ => Err("Locale-specific character classes not supported".to_string()),
+            name => match config.class(name) {
+                Some(ranges) => Ok(Literal(Class {
+                    name: name.to_string(),
+                    ranges: ranges.clone(),
+                })),
+                None => Err("Locale-specific character classes not supported".to_string()),
+            },
         }
     }
 
-    fn parse_bracket(chars: &mut Peekable<Chars>) -> Result<Vec<Token>, String> {
+    /// Reads a bracket expression whose `[` was just consumed. A first
+    /// character in `negation` negates it.
+    ///
+    /// The `Or` and `Not` wrappers and the delimiters between items carry
+    /// the span of the whole bracket expression.
+    fn parse_bracket(chars: &mut Cursor, config: &ParserConfig, negation: &[char],
+        escape: Option<char>) -> Result<Spanned, String> {
         token_use!();
-        let mut toks = if Some(&'^') == chars.peek() {
-            chars.next();
-            vec![Grouping(Begin(GBegin::Not)), Grouping(Begin(GBegin::Or))]
-        } else {
-            vec![Grouping(Begin(GBegin::Or))]
+        let start = chars.pos - 1;
+        let negated = match chars.peek() {
+            Some(c) if negation.contains(&c) => {
+                chars.next();
+                true
+            },
+            _ => false,
         };
 
-        if Some(&']') == chars.peek() {
+        let mut items: Spanned = Vec::new();
+        if chars.peek() == Some(']') {
             chars.next();
-
-            toks.push(Literal(Char(']')));
-            toks.push(Grouping(OrDelimiter));
+            items.push((Literal(Char(']')), chars.pos - 1..chars.pos));
         }
 
-        while let Some(c) = chars.next() {
+        loop {
+            let at = chars.pos;
+            let c = match chars.next() {
+                Some(c) => c,
+                None => break,
+            };
+
+            if Some(c) == escape {
+                match chars.next() {
+                    Some(escaped) => items.push((Literal(Char(escaped)), at..chars.pos)),
+                    // Return an Err after the loop
+                    None => break,
+                }
+                continue;
+            }
+
             match c {
                 '[' => {
-                    if let Some(&next_char) = chars.peek() {
-                        match next_char {
-                            '.' => return Err("Collating elements not supported".to_string()),
-                            '=' => return Err("Equivalence classes not supported".to_string()),
-                            ':' => {
-                                let class_tok = TokenParser::parse_char_class(chars)?;
-                                toks.push(class_tok);
-                                toks.push(Grouping(OrDelimiter));
-                            },
-                            _ => {
-                                toks.push(Literal(Char(c)));
-                                toks.push(Grouping(OrDelimiter));
-                            },
-                        }
+                    match chars.peek() {
+                        Some('.') => return Err("Collating elements not supported".to_string()),
+                        Some('=') => return Err("Equivalence classes not supported".to_string()),
+                        Some(':') => {
+                            let class_tok = TokenParser::parse_char_class(chars, config)?;
+                            items.push((class_tok, at..chars.pos));
+                        },
+                        Some(_) => items.push((Literal(Char(c)), at..chars.pos)),
+                        // Return an Err after the loop
+                        None => break,
                     }
-                    // Do nothing on `else`, since we'll return Err after `while`
                 },
                 ']' => {
-                    // Remove trailing Or
-                    toks.pop();
+                    let span = start..chars.pos;
+                    let mut toks = Vec::with_capacity(items.len() * 2 + 1);
+                    for (i, item) in items.into_iter().enumerate() {
+                        if i > 0 {
+                            toks.push((Grouping(OrDelimiter), span.clone()));
+                        }
+                        toks.push(item);
+                    }
 
-                    toks.push(Grouping(End(GEnd::Or)));
-                    if let Some(&Grouping(Begin(GBegin::Not))) = toks.first() {
-                        toks.push(Grouping(End(GEnd::Not)));
+                    let toks = wrap_span(GBegin::Or, GEnd::Or, span.clone(), toks);
+                    if negated {
+                        return Ok(wrap_span(GBegin::Not, GEnd::Not, span, toks));
                     }
                     return Ok(toks);
                 },
-                '-' => {
-                    // If we have something before this and there is more after
-                    if Some(&Grouping(OrDelimiter)) == toks.last()
-                        && Some(&']') != chars.peek() {
-
-                        if let Some(next_char) = chars.next() {
-                            let next_is_period = Some(&'.') == chars.peek();
-
-                            match next_char {
-                                '[' if next_is_period => {
-                                    return Err("Collating elements not supported".to_string());
-                                },
-                                _ => {
-                                    // Remove OrDelimiter
-                                    toks.pop();
-                                    if let Some(Literal(Char(first))) = toks.pop() {
-                                        toks.push(Literal(Range {
-                                            begin: first,
-                                            end: next_char,
-                                        }));
-                                        toks.push(Grouping(OrDelimiter));
-                                    } else {
-                                        return Err("Something went wrong on our end".to_string());
-                                    }
-                                },
-                            }
-                        } else {
-                            // Return an Err
-                            break;
-                        }
+                // If we have something before this and there is more after
+                '-' if !items.is_empty() && chars.peek() != Some(']') => {
+                    let mut next_char = match chars.next() {
+                        Some(next_char) => next_char,
+                        // Return an Err after the loop
+                        None => break,
+                    };
+                    if Some(next_char) == escape {
+                        next_char = match chars.next() {
+                            Some(escaped) => escaped,
+                            None => break,
+                        };
+                    } else if next_char == '[' && chars.peek() == Some('.') {
+                        return Err("Collating elements not supported".to_string());
+                    }
 
-                // If the '-' is the first or last thing in bracket expression
-                    } else {
-                        // Use it as the literal char
-                        toks.push(Literal(Char(c)));
-                        toks.push(Grouping(OrDelimiter));
+                    match items.pop() {
+                        Some((Literal(Char(first)), _)) if first > next_char => {
+                            return Err(format!("Range {}-{} is backwards", first, next_char));
+                        },
+                        Some((Literal(Char(first)), first_span)) => {
+                            let range = Literal(Range { begin: first, end: next_char });
+                            items.push((range, first_span.start..chars.pos));
+                        },
+                        _ => return Err("Something went wrong on our end".to_string()),
                     }
                 },
-                _ => {
-                    toks.push(Literal(Char(c)));
-                    toks.push(Grouping(OrDelimiter));
-                },
+                // Otherwise, including when the '-' is the first or last
+                // thing in the bracket expression, it's a literal char
+                _ => items.push((Literal(Char(c)), at..chars.pos)),
+            }
+        }
+
+        Err("Pattern ended in middle of bracket expression".to_string())
+    }
+
+    /// Builds a parser from front-end output, splitting off the spans
+    fn spanned(source: &str, toks: Spanned) -> TokenParser {
+        let (tokens, spans) = toks.into_iter().unzip();
+        TokenParser {
+            pattern_source: source.to_string(),
+            tokens,
+            spans,
+        }
+    }
 
+    /// For streams without a source: the source is the stream printed in
+    /// the native syntax, and the spans are where each token is in it. If it
+    /// can't be printed, or doesn't read back as the same tokens, the source
+    /// is empty and so is every span. The tokens are always `tokens`.
+    fn printed(tokens: Vec<Token>) -> TokenParser {
+        let mut config = ParserConfig::new();
+        config.operators(true);
+        if let Ok(source) = to_pattern(&tokens) {
+            if let Ok(parser) = TokenParser::from_string_with(&source, &config) {
+                if parser.tokens == tokens {
+                    return parser;
+                }
             }
         }
 
-        return Err("Pattern ended in middle of bracket expression".to_string());
+        TokenParser {
+            pattern_source: String::new(),
+            spans: vec![0..0; tokens.len()],
+            tokens,
+        }
     }
 
     pub fn from_string(pat: &str) -> Result<TokenParser, String> {
-        token_use!();
+        TokenParser::from_string_with(pat, &ParserConfig::default())
+    }
 
-        let mut open_groups: Vec<usize> = Vec::new();
-        let mut toks: Vec<Token> = vec![ Grouping(Begin(GBegin::Pat)) ];
+    pub fn from_string_with(pat: &str, config: &ParserConfig)
+        -> Result<TokenParser, String> {
+        let toks = match config.dialect {
+            Dialect::Native => TokenParser::parse_native(pat, config)?,
+            Dialect::Bre => posix::parse_bre(pat, config)?,
+            Dialect::Ere => posix::parse_ere(pat, config)?,
+        };
+        Ok(TokenParser::spanned(pat, toks))
+    }
 
-        let mut chars = pat.chars().peekable();
+    /// Parses a shell or gitignore style glob (see the `glob` module) into a
+    /// stream that matches whole `/` separated paths.
+    pub fn from_glob(glob: &str) -> Result<TokenParser, String> {
+        Ok(TokenParser::spanned(glob, glob::parse_glob(glob)?))
+    }
 
+    /// Parses the pattern of an SQL `LIKE` predicate, with the character
+    /// from its `ESCAPE` clause if it has one.
+    pub fn from_like(pat: &str, escape: Option<char>) -> Result<TokenParser, String> {
+        Ok(TokenParser::spanned(pat, sql::parse_like(pat, escape)?))
+    }
 
-        'source_loop: while let Some(c) = chars.next() {
-            match c {
-                '(' => {
-                    if chars.peek() == Some(&':') {
-                        chars.next();
-                        if let Some(&next_char) = chars.peek() {
-                            match next_char {
-                                '<' => {
-                                    let name = TokenParser::read_cap_name(&mut chars)?;
-                                    let name = Some(name);
-                                    toks.push(Grouping(Begin(GBegin::Cap { name: name })));
-                                    open_groups.push(toks.len() - 1);
-                                },
-                                _ =>
-                                    return Err(format!("Special capture group (:{} not implented", next_char)),
-                            }
-                        } else {
-                            return Err("Unclosed capture group".to_string());
-                        }
+    /// Parses the pattern of an SQL `SIMILAR TO` predicate, with the
+    /// character from its `ESCAPE` clause if it has one.
+    pub fn from_similar_to(pat: &str, escape: Option<char>) -> Result<TokenParser, String> {
+        Ok(TokenParser::spanned(pat, sql::parse_similar_to(pat, escape)?))
+    }
 
-                    } else {
-                        toks.push(Grouping(Begin(GBegin::Cap { name: None })));
-                        open_groups.push(toks.len() - 1);
-                    }
-                },
-                ')' => {
-                    let mut index = open_groups.pop().ok_or("Group closed without being opened".to_string())?;
+    /// Takes a token stream built or changed by hand, after checking that it
+    /// is well-formed (see the `validate` module). Its source is the pattern
+    /// in the native syntax, or empty if it can't be written in it.
+    pub fn from_tokens(tokens: Vec<Token>) -> Result<TokenParser, TokenError> {
+        validate::validate(&tokens)?;
+        Ok(TokenParser::printed(tokens))
+    }
 
-                    if let &Grouping(Begin(GBegin::Or)) = &toks[index] {
-                        toks.push(Grouping(End(GEnd::Or)));
-                        index = open_groups.pop().ok_or("Group closed without being opened".to_string())?;
-                    }
-                    let index = index;
+    /// The tokens of a pattern built in code. Its source is the pattern in
+    /// the native syntax, or empty if it can't be written in it.
+    pub fn from_pattern(pattern: &Pattern) -> TokenParser {
+        TokenParser::printed(pattern.to_tokens())
+    }
 
-                    if let &Grouping(Begin(GBegin::Cap {..})) = &toks[index] {
-                        toks.push(Grouping(End(GEnd::Cap)));
-                    } else {
-                        return Err("Closed group when other things are open".to_string());
-                    }
+    pub fn pattern_source(&self) -> &str {
+        &self.pattern_source
+    }
 
-                },
-                '?' => {
-                    let index = TokenParser::prev_object(&mut toks);
-                    toks.insert(index, Grouping(Begin(GBegin::Maybe)));
-                    toks.push(Grouping(End(GEnd::Maybe)));
-                },
-                '+' => {
-                    let index = TokenParser::prev_object(&mut toks);
-                    toks.insert(index, Grouping(Begin(GBegin::Always)));
-                    toks.push(Grouping(End(GEnd::Always)));
-                },
-                '*' => {
-                    let index = TokenParser::prev_object(&mut toks);
-                    toks.insert(index, Grouping(Begin(GBegin::Any)));
-                    toks.push(Grouping(End(GEnd::Any)));
-                },
-                '|' => {
-                    toks.push(Grouping(OrDelimiter));
-
-                    let index = if let Some(&index) = open_groups.last() {
-                        match toks[index] {
-                            Grouping(Begin(GBegin::Cap {..})) => index + 1,
-                            Grouping(Begin(GBegin::Or)) => continue 'source_loop,
-                            _ => 1,
-                        }
-                    } else {
-                        1
-                    };
-                    let index = index;
-                    toks.insert(index, Grouping(Begin(GBegin::Or)));
-                    open_groups.push(index);
-                },
-                '[' => {
-                    let range_toks = TokenParser::parse_bracket(&mut chars)?;
-                    toks.extend(range_toks);
-                },
-                '^' => toks.push(Literal(StartOfLine)),
-                '$' => toks.push(Literal(EndOfLine)),
-                '.' => toks.push(Literal(AnyChar)),
-                '\\' => {
-                    if let Some(next_char) = chars.next() {
-                        if ESCAPABLE_CHARS.contains(&next_char) {
-                            toks.push(Literal(Char(next_char)));
-                        } else {
-                            match next_char {
-                                'w' => toks.push(Literal(Alnum)), //word
-                                'W' => {
-                                    toks.push(Grouping(Begin(GBegin::Not)));
-                                    toks.push(Literal(Alnum));
-                                    toks.push(Grouping(End(GEnd::Not)));
-                                }, //not word
-                                'd' => toks.push(Literal(Digit)), //digit
-                                'D' => {
-                                    toks.push(Grouping(Begin(GBegin::Not)));
-                                    toks.push(Literal(Digit));
-                                    toks.push(Grouping(End(GEnd::Not)));
-                                }, //not digit
-                                's' => toks.push(Literal(Whitespace)), //Whitespace
-                                'S' => {
-                                    toks.push(Grouping(Begin(GBegin::Not)));
-                                    toks.push(Literal(Whitespace));
-                                    toks.push(Grouping(End(GEnd::Not)));
-                                }, //not Whitespace
-                                _ => return Err(format!("Character class {} not implemented", next_char)),
-                            }
-                        }
-                    } else {
-                        return Err("Pattern ended when expecting escaped character".to_string());
-                    }
-                },
-                _ => toks.push(Literal(Char(c))),
-            }
+    /// The parsed tokens. They can't be changed in place, which would leave
+    /// the spans describing other tokens; build a new parser with
+    /// `from_tokens` instead.
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    pub fn into_tokens(self) -> Vec<Token> {
+        self.tokens
+    }
+
+    /// The byte range of `pattern_source` each token came from, in the same
+    /// order as `tokens`.
+    ///
+    /// Tokens the syntax implies rather than spells out carry the span of
+    /// the construct that implies them: the `Not` and `Alnum` of `\W` both
+    /// cover `\W`, the `Or` around a bracket expression and its delimiters
+    /// cover the whole `[...]`, and the group of a quantifier covers the
+    /// quantifier and its operand. Anchors added by the glob and SQL
+    /// front-ends are empty spans at either end.
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    /// The tokens written back out as a native pattern, which may differ
+    /// from the text they were parsed from. It fails for the streams
+    /// `to_pattern` can't print, like most glob and SQL ones.
+    pub fn to_pattern(&self) -> Result<String, String> {
+        to_pattern(&self.tokens)
+    }
+
+    fn parse_native(pat: &str, config: &ParserConfig) -> Result<Spanned, String> {
+        let mut parser = NativeParser {
+            chars: Cursor::new(pat),
+            config,
+        };
+
+        let toks = parser.alternation(false)?;
+        Ok(wrap_span(GBegin::Pat, GEnd::Pat, 0..pat.len(), toks))
+    }
+}
+
+/// Recursive descent over the native syntax, lowest precedence first:
+///
+/// ```text
+/// alternation  := intersection ('|' intersection)*
+/// intersection := sequence ('&' sequence)*
+/// sequence     := (atom ('?' | '+' | '*')*)*
+/// atom         := '(' [':<' name '>'] alternation ')' | '~(' alternation ')'
+///               | '[' bracket | '\' escape | char
+/// ```
+///
+/// `&` and `~` are only special with `ParserConfig::operators`.
+struct NativeParser<'a, 'c> {
+    chars: Cursor<'a>,
+    config: &'c ParserConfig,
+}
+
+impl<'a, 'c> NativeParser<'a, 'c> {
+    /// Reads `|` separated sequences up to the end of the pattern or, inside
+    /// a group, up to its `)`. More than one sequence makes an `Or` group.
+    fn alternation(&mut self, in_group: bool) -> Result<Spanned, String> {
+        token_use!();
+        let start = self.chars.pos;
+        let mut toks = self.intersection(in_group)?;
+        if self.chars.peek() != Some('|') {
+            return Ok(toks);
+        }
+
+        while self.chars.eat("|") {
+            toks.push((Grouping(OrDelimiter), self.chars.pos - 1..self.chars.pos));
+            toks.extend(self.intersection(in_group)?);
         }
+        Ok(wrap_span(GBegin::Or, GEnd::Or, start..self.chars.pos, toks))
+    }
 
-        if let Some(&index) = open_groups.last() {
-            println!("Last open group = {:?}", toks[index]);
-            match toks[index] {
-                Grouping(Begin(GBegin::Or)) => {
-                    toks.push(Grouping(End(GEnd::Or)));
-                    open_groups.pop();
+    /// Reads `&` separated sequences. More than one makes an `And` group.
+    fn intersection(&mut self, in_group: bool) -> Result<Spanned, String> {
+        token_use!();
+        let start = self.chars.pos;
+        let mut toks = self.sequence(in_group)?;
+        if !self.config.operators || self.chars.peek() != Some('&') {
+            return Ok(toks);
+        }
+
+        while self.chars.eat("&") {
+            toks.push((Grouping(AndDelimiter), self.chars.pos - 1..self.chars.pos));
+            toks.extend(self.sequence(in_group)?);
+        }
+        Ok(wrap_span(GBegin::And, GEnd::And, start..self.chars.pos, toks))
+    }
+
+    fn sequence(&mut self, in_group: bool) -> Result<Spanned, String> {
+        token_use!();
+        // Quantifiers wrap the last atom, so keep atoms apart until the end
+        let mut atoms: Vec<Spanned> = Vec::new();
+
+        while let Some(c) = self.chars.peek() {
+            let at = self.chars.pos;
+            let quantifier = match c {
+                '|' => break,
+                '&' if self.config.operators => break,
+                ')' if in_group => break,
+                ')' => return Err("Group closed without being opened".to_string()),
+                '?' => Some((GBegin::Maybe, GEnd::Maybe)),
+                '+' => Some((GBegin::Always, GEnd::Always)),
+                '*' => Some((GBegin::Any, GEnd::Any)),
+                _ => None,
+            };
+            self.chars.next();
+
+            match quantifier {
+                Some((begin, end)) => {
+                    let operand = atoms.pop()
+                        .ok_or(format!("Quantifier {} has nothing to repeat", c))?;
+                    let span = operand[0].1.start..self.chars.pos;
+                    atoms.push(wrap_span(begin, end, span, operand));
                 },
-                _ => (),
+                None => self.atom(c, at, &mut atoms)?,
             }
         }
 
-        if open_groups.is_empty() {
-            toks.push(Grouping(End(GEnd::Pat)));
-            let parser = TokenParser {
-                pattern_source: pat.to_string(),
-                tokens: toks,
-            };
+        Ok(atoms.concat())
+    }
 
-            Ok(parser)
-        } else {
-            Err("Pattern ended with unclosed groups".to_string())
+    /// Reads the atom starting with `c`, which was just consumed from `at`.
+    /// `\Q...\E` adds one atom per quoted character.
+    fn atom(&mut self, c: char, at: usize, atoms: &mut Vec<Spanned>) -> Result<(), String> {
+        token_use!();
+        let atom = match c {
+            '(' => {
+                let name = self.cap_name()?;
+                let inner = self.alternation(true)?;
+                if self.chars.next() != Some(')') {
+                    return Err("Pattern ended with unclosed groups".to_string());
+                }
+                wrap_span(GBegin::Cap { name }, GEnd::Cap, at..self.chars.pos, inner)
+            },
+            '~' if self.config.operators => {
+                if !self.chars.eat("(") {
+                    return Err("~ must be followed by (".to_string());
+                }
+                let inner = self.alternation(true)?;
+                if self.chars.next() != Some(')') {
+                    return Err("Pattern ended with unclosed groups".to_string());
+                }
+                wrap_span(GBegin::Complement, GEnd::Complement, at..self.chars.pos, inner)
+            },
+            '[' => TokenParser::parse_bracket(&mut self.chars, self.config, &['^'], None)?,
+            '^' => vec![(Literal(StartOfLine), at..self.chars.pos)],
+            '$' => vec![(Literal(EndOfLine), at..self.chars.pos)],
+            '.' => vec![(Literal(AnyChar), at..self.chars.pos)],
+            '\\' => return self.escape(at, atoms),
+            _ => vec![(Literal(Char(c)), at..self.chars.pos)],
+        };
+        atoms.push(atom);
+        Ok(())
+    }
+
+    /// Reads the `:<name>` that can follow a `(`
+    fn cap_name(&mut self) -> Result<Option<String>, String> {
+        if !self.chars.eat(":") {
+            return Ok(None);
+        }
+
+        match self.chars.peek() {
+            Some('<') => {
+                let mut rest = self.chars.rest().chars().peekable();
+                let name = TokenParser::read_cap_name(&mut rest)?;
+                self.chars.pos += name.len() + 2; // `<` and `>`
+                Ok(Some(name))
+            },
+            Some(next_char) =>
+                Err(format!("Special capture group (:{} not implented", next_char)),
+            None => Err("Unclosed capture group".to_string()),
         }
     }
+
+    fn escape(&mut self, at: usize, atoms: &mut Vec<Spanned>) -> Result<(), String> {
+        token_use!();
+        let next_char = self.chars.next()
+            .ok_or("Pattern ended when expecting escaped character".to_string())?;
+        let span = at..self.chars.pos;
+        let not = |lit| wrap_span(GBegin::Not, GEnd::Not, span.clone(), vec![(Literal(lit), span.clone())]);
+
+        let atom = match next_char {
+            c if ESCAPABLE_CHARS.contains(&c) => vec![(Literal(Char(c)), span)],
+            'w' => vec![(Literal(Alnum), span)], //word
+            'W' => not(Alnum), //not word
+            'd' => vec![(Literal(Digit), span)], //digit
+            'D' => not(Digit), //not digit
+            's' => vec![(Literal(Whitespace), span)], //Whitespace
+            'S' => not(Whitespace), //not Whitespace
+            'Q' => {
+                // Everything up to `\E` (or the end of the pattern) is taken
+                // literally
+                loop {
+                    let at = self.chars.pos;
+                    match self.chars.next() {
+                        Some('\\') if self.chars.eat("E") => break,
+                        Some(quoted) => atoms.push(vec![(Literal(Char(quoted)), at..self.chars.pos)]),
+                        None => break,
+                    }
+                }
+                return Ok(());
+            },
+            _ => return Err(format!("Character class {} not implemented", next_char)),
+        };
+        atoms.push(atom);
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -454,18 +899,6 @@ mod tests {
                 assert_eq!(expected_toks, par.tokens);
             }
         }
-
-        #[test]
-        fn prev_object() {
-            use stream;
-            token_use!();
-            let toks = vec![Grouping(Begin(GBegin::Pat)),
-                Literal(Char('a')), Literal(Char('b')),
-                Literal(Char('c'))];
-
-            let index = stream::TokenParser::prev_object(&toks);
-            assert_eq!(3, index);
-        }
     }
 
     mod capture_group {
@@ -951,4 +1384,435 @@ mod tests {
             assert_eq!(expected_err, err);
         }
     }
+
+    mod config {
+        #[test]
+        fn registered_class() {
+            use stream;
+            token_use!();
+
+            let mut config = stream::ParserConfig::new();
+            config.register_class("hexupper", &[('0', '9'), ('A', 'F')]).unwrap();
+
+            let pat = "a[[:hexupper:]x]";
+            let expected_toks = vec![Grouping(Begin(GBegin::Pat)),
+                Literal(Char('a')), Grouping(Begin(GBegin::Or)),
+                Literal(Class {
+                    name: "hexupper".to_string(),
+                    ranges: vec![('0', '9'), ('A', 'F')],
+                }),
+                Grouping(OrDelimiter), Literal(Char('x')),
+                Grouping(End(GEnd::Or)), Grouping(End(GEnd::Pat))];
+
+            let name = stream::TokenParser::from_string_with(pat, &config).unwrap();
+            assert_eq!(expected_toks, name.tokens);
+        }
+
+        #[test]
+        fn operators() {
+            use stream;
+            token_use!();
+
+            let mut config = stream::ParserConfig::new();
+            config.operators(true);
+
+            let pat = "ab&~(c)|d";
+            let expected_toks = vec![Grouping(Begin(GBegin::Pat)),
+                Grouping(Begin(GBegin::Or)), Grouping(Begin(GBegin::And)),
+                Literal(Char('a')), Literal(Char('b')), Grouping(AndDelimiter),
+                Grouping(Begin(GBegin::Complement)), Literal(Char('c')),
+                Grouping(End(GEnd::Complement)), Grouping(End(GEnd::And)),
+                Grouping(OrDelimiter), Literal(Char('d')),
+                Grouping(End(GEnd::Or)), Grouping(End(GEnd::Pat))];
+
+            let name = stream::TokenParser::from_string_with(pat, &config).unwrap();
+            assert_eq!(expected_toks, name.tokens);
+            assert_eq!(&[0..9, 0..7, 0..1][..], &name.spans()[1..4]);
+
+            let literal = stream::TokenParser::from_string(r"a&~\&").unwrap();
+            assert_eq!(vec![Grouping(Begin(GBegin::Pat)), Literal(Char('a')),
+                Literal(Char('&')), Literal(Char('~')), Literal(Char('&')),
+                Grouping(End(GEnd::Pat))], literal.tokens);
+            assert_eq!(Err("~ must be followed by (".to_string()),
+                stream::TokenParser::from_string_with("~a", &config));
+        }
+
+        #[test]
+        fn unregistered_class() {
+            use stream;
+
+            let mut config = stream::ParserConfig::new();
+            config.register_class("hexupper", &[('A', 'F')]).unwrap();
+
+            let err = stream::TokenParser::from_string_with("[[:ident_start:]]", &config);
+            let expected_err = Err("Locale-specific character classes not supported".to_string());
+            assert_eq!(expected_err, err);
+        }
+
+        #[test]
+        fn builtin_class_still_parses() {
+            use stream;
+            token_use!();
+
+            let mut config = stream::ParserConfig::new();
+            config.register_class("ident_start", &[('a', 'z'), ('_', '_')]).unwrap();
+
+            let name = stream::TokenParser::from_string_with("[[:digit:]]", &config).unwrap();
+            assert_eq!(Literal(Digit), name.tokens[2]);
+        }
+
+        #[test]
+        fn invalid_registrations() {
+            use stream;
+
+            let mut config = stream::ParserConfig::new();
+            assert!(config.register_class("", &[('a', 'z')]).is_err());
+            assert!(config.register_class("a:b", &[('a', 'z')]).is_err());
+            assert!(config.register_class("alpha", &[('a', 'z')]).is_err());
+            assert!(config.register_class("none", &[]).is_err());
+            assert!(config.register_class("backwards", &[('z', 'a')]).is_err());
+            assert_eq!(stream::ParserConfig::new(), config);
+        }
+    }
+
+    mod quote {
+        use proptest::prelude::*;
+
+        #[test]
+        fn quoted_specials() {
+            use stream;
+            token_use!();
+            let pat = r"a\Q(b|*)\Ec";
+            let expected_toks = vec![Grouping(Begin(GBegin::Pat)),
+                Literal(Char('a')), Literal(Char('(')),
+                Literal(Char('b')), Literal(Char('|')),
+                Literal(Char('*')), Literal(Char(')')),
+                Literal(Char('c')), Grouping(End(GEnd::Pat))];
+
+            let name = stream::TokenParser::from_string(pat).unwrap();
+            assert_eq!(expected_toks, name.tokens);
+        }
+
+        #[test]
+        fn quote_without_end() {
+            use stream;
+            token_use!();
+            let pat = r"\Qa\b";
+            let expected_toks = vec![Grouping(Begin(GBegin::Pat)),
+                Literal(Char('a')), Literal(Char('\\')),
+                Literal(Char('b')), Grouping(End(GEnd::Pat))];
+
+            let name = stream::TokenParser::from_string(pat).unwrap();
+            assert_eq!(expected_toks, name.tokens);
+        }
+
+        #[test]
+        fn escape_specials() {
+            use stream;
+            assert_eq!(r"a\.b\*\\\(", stream::escape(r"a.b*\("));
+            assert_eq!("", stream::escape(""));
+        }
+
+        fn literal_toks(text: &str) -> Vec<::stream::Token> {
+            token_use!();
+            let mut toks = vec![Grouping(Begin(GBegin::Pat))];
+            toks.extend(text.chars().map(|c| Literal(Char(c))));
+            toks.push(Grouping(End(GEnd::Pat)));
+            toks
+        }
+
+        proptest! {
+            #[test]
+            fn escaped_is_literal(text in ".*") {
+                use stream;
+                let par = stream::TokenParser::from_string(&stream::escape(&text)).unwrap();
+                prop_assert_eq!(literal_toks(&text), par.tokens);
+            }
+
+            #[test]
+            fn quoted_is_literal(text in ".*") {
+                use stream;
+                prop_assume!(!text.contains(r"\E"));
+                let pattern = format!(r"\Q{}\E", text);
+                let par = stream::TokenParser::from_string(&pattern).unwrap();
+                prop_assert_eq!(literal_toks(&text), par.tokens);
+            }
+        }
+    }
+
+    mod precedence {
+        #[test]
+        fn alternation_in_quantified_group() {
+            use stream;
+            token_use!();
+            let pat = "(a|b)*c";
+            let expected_toks = vec![Grouping(Begin(GBegin::Pat)),
+                Grouping(Begin(GBegin::Any)), Grouping(Begin(GBegin::Cap {name: None})),
+                Grouping(Begin(GBegin::Or)), Literal(Char('a')),
+                Grouping(OrDelimiter), Literal(Char('b')),
+                Grouping(End(GEnd::Or)), Grouping(End(GEnd::Cap)),
+                Grouping(End(GEnd::Any)), Literal(Char('c')),
+                Grouping(End(GEnd::Pat))];
+
+            let name = stream::TokenParser::from_string(pat).unwrap();
+            assert_eq!(expected_toks, name.tokens);
+        }
+
+        #[test]
+        fn quantifier_binds_tighter_than_or() {
+            use stream;
+            token_use!();
+            let pat = "a|b*";
+            let expected_toks = vec![Grouping(Begin(GBegin::Pat)),
+                Grouping(Begin(GBegin::Or)), Literal(Char('a')),
+                Grouping(OrDelimiter), Grouping(Begin(GBegin::Any)),
+                Literal(Char('b')), Grouping(End(GEnd::Any)),
+                Grouping(End(GEnd::Or)), Grouping(End(GEnd::Pat))];
+
+            let name = stream::TokenParser::from_string(pat).unwrap();
+            assert_eq!(expected_toks, name.tokens);
+        }
+
+        #[test]
+        fn nested_alternations() {
+            use stream;
+            token_use!();
+            let pat = "((a|b)c|d)e";
+            let expected_toks = vec![Grouping(Begin(GBegin::Pat)),
+                Grouping(Begin(GBegin::Cap {name: None})), Grouping(Begin(GBegin::Or)),
+                Grouping(Begin(GBegin::Cap {name: None})), Grouping(Begin(GBegin::Or)),
+                Literal(Char('a')), Grouping(OrDelimiter), Literal(Char('b')),
+                Grouping(End(GEnd::Or)), Grouping(End(GEnd::Cap)),
+                Literal(Char('c')), Grouping(OrDelimiter), Literal(Char('d')),
+                Grouping(End(GEnd::Or)), Grouping(End(GEnd::Cap)),
+                Literal(Char('e')), Grouping(End(GEnd::Pat))];
+
+            let name = stream::TokenParser::from_string(pat).unwrap();
+            assert_eq!(expected_toks, name.tokens);
+        }
+
+        #[test]
+        fn stacked_quantifiers() {
+            use stream;
+            token_use!();
+            let pat = r"\W+?";
+            let expected_toks = vec![Grouping(Begin(GBegin::Pat)),
+                Grouping(Begin(GBegin::Maybe)), Grouping(Begin(GBegin::Always)),
+                Grouping(Begin(GBegin::Not)), Literal(Alnum),
+                Grouping(End(GEnd::Not)), Grouping(End(GEnd::Always)),
+                Grouping(End(GEnd::Maybe)), Grouping(End(GEnd::Pat))];
+
+            let name = stream::TokenParser::from_string(pat).unwrap();
+            assert_eq!(expected_toks, name.tokens);
+        }
+
+        #[test]
+        fn quantified_quote_repeats_last_char() {
+            use stream;
+            token_use!();
+            let pat = r"\Qab\E+";
+            let expected_toks = vec![Grouping(Begin(GBegin::Pat)),
+                Literal(Char('a')), Grouping(Begin(GBegin::Always)),
+                Literal(Char('b')), Grouping(End(GEnd::Always)),
+                Grouping(End(GEnd::Pat))];
+
+            let name = stream::TokenParser::from_string(pat).unwrap();
+            assert_eq!(expected_toks, name.tokens);
+        }
+
+        #[test]
+        fn quantifier_without_operand() {
+            use stream;
+            for pat in &["*a", "a|+b", "(?a)", "^|*"] {
+                let err = stream::TokenParser::from_string(pat);
+                assert!(err.is_err(), "{} should not parse", pat);
+            }
+        }
+
+        #[test]
+        fn unbalanced_groups() {
+            use stream;
+            assert_eq!(Err("Group closed without being opened".to_string()),
+                stream::TokenParser::from_string("a)b"));
+            assert_eq!(Err("Pattern ended with unclosed groups".to_string()),
+                stream::TokenParser::from_string("(a|b"));
+        }
+    }
+
+    mod spans {
+        use proptest::prelude::*;
+
+        use ast::{Ast, Repetition};
+        use graph::Assertion;
+        use stream::{self, TokenParser};
+
+        /// Trees whose token streams are well-formed, `Complement` and `And`
+        /// included
+        fn arb_ast() -> impl Strategy<Value = Ast> {
+            let leaf = prop_oneof![
+                prop::char::range('a', 'c').prop_map(Ast::Literal),
+                prop::sample::select(vec!['~', '&', '(', '|', ':']).prop_map(Ast::Literal),
+                Just(Ast::Class(stream::Literal::AnyChar)),
+                Just(Ast::Class(stream::Literal::Digit)),
+                Just(Ast::Class(stream::Literal::Range { begin: 'a', end: 'c' })),
+                Just(Ast::Assertion(Assertion::StartOfLine)),
+                Just(Ast::Assertion(Assertion::EndOfText)),
+            ];
+            let repetition = prop::sample::select(vec![Repetition::Maybe, Repetition::Always,
+                Repetition::Any]);
+            leaf.prop_recursive(4, 24, 3, move |inner| prop_oneof![
+                prop::collection::vec(inner.clone(), 0..3).prop_map(Ast::Concat),
+                prop::collection::vec(inner.clone(), 1..3).prop_map(Ast::Alternation),
+                prop::collection::vec(inner.clone(), 1..3).prop_map(Ast::Intersection),
+                // A quantifier holds exactly one item
+                (repetition.clone(), inner.clone()).prop_map(|(kind, ast)| match ast {
+                    Ast::Concat(_) => Ast::Repetition(kind, Box::new(Ast::Group(None, Box::new(ast)))),
+                    ast => Ast::Repetition(kind, Box::new(ast)),
+                }),
+                (prop::option::of(Just("n".to_string())), inner.clone())
+                    .prop_map(|(name, ast)| Ast::Group(name, Box::new(ast))),
+                inner.clone().prop_map(|ast| Ast::Not(Box::new(ast))),
+                inner.prop_map(|ast| Ast::Complement(Box::new(ast))),
+            ])
+        }
+
+        /// The text each token came from
+        fn texts(parser: &TokenParser) -> Vec<&str> {
+            parser.spans().iter().map(|span| &parser.pattern_source()[span.clone()]).collect()
+        }
+
+        #[test]
+        fn synthesized_tokens() {
+            let parser = TokenParser::from_string(r"a\W[b-c]*").unwrap();
+            assert_eq!(vec![r"a\W[b-c]*", "a", r"\W", r"\W", r"\W",
+                "[b-c]*", "[b-c]", "b-c", "[b-c]", "[b-c]*", r"a\W[b-c]*"], texts(&parser));
+        }
+
+        #[test]
+        fn groups_and_alternation() {
+            let parser = TokenParser::from_string(r"(:<n>x|yz)?").unwrap();
+            assert_eq!(vec!["(:<n>x|yz)?", "(:<n>x|yz)?", "(:<n>x|yz)", "x|yz", "x", "|",
+                "y", "z", "x|yz", "(:<n>x|yz)", "(:<n>x|yz)?", "(:<n>x|yz)?"], texts(&parser));
+
+            let quoted = TokenParser::from_string(r"\Qa.\E").unwrap();
+            assert_eq!(vec![r"\Qa.\E", "a", ".", r"\Qa.\E"], texts(&quoted));
+        }
+
+        #[test]
+        fn other_front_ends() {
+            let glob = TokenParser::from_glob("a/*").unwrap();
+            assert_eq!(vec!["a/*", "", "a", "/", "*", "*", "*", "*", "*", "", "a/*"], texts(&glob));
+            assert_eq!(3..3, glob.spans()[9]);
+
+            let similar = TokenParser::from_similar_to("b{1,2}", None).unwrap();
+            assert_eq!(vec!["b{1,2}", "", "b", "b{1,2}", "b", "b{1,2}", "", "b{1,2}"], texts(&similar));
+        }
+
+        #[test]
+        fn streams_without_source() {
+            let parser = TokenParser::from_string("[ab]").unwrap();
+            let rebuilt = TokenParser::from_tokens(parser.into_tokens()).unwrap();
+            assert_eq!("a|b", rebuilt.pattern_source());
+            assert_eq!(vec!["a|b", "a|b", "a", "|", "b", "a|b", "a|b"], texts(&rebuilt));
+
+            let mut config = ::stream::ParserConfig::new();
+            config.operators(true);
+            let operators = TokenParser::from_string_with("~(ab)&a*", &config).unwrap();
+            let rebuilt = TokenParser::from_tokens(operators.tokens().to_vec()).unwrap();
+            assert_eq!(operators.tokens(), rebuilt.tokens());
+            assert_eq!("~(ab)&a*", rebuilt.pattern_source());
+
+            let like = TokenParser::from_like("_", None).unwrap();
+            let unprintable = TokenParser::from_tokens(like.into_tokens()).unwrap();
+            assert!(unprintable.spans().iter().all(|span| *span == (0..0)));
+        }
+
+        proptest! {
+            #[test]
+            fn built_tokens_are_kept(ast in arb_ast()) {
+                let toks = ast.to_tokens();
+                let parser = TokenParser::from_tokens(toks.clone()).unwrap();
+                prop_assert_eq!(&toks[..], parser.tokens());
+                prop_assert_eq!(toks.len(), parser.spans().len());
+
+                // Whatever can be printed has to read back the same
+                if let Ok(printed) = stream::to_pattern(&toks) {
+                    let mut config = stream::ParserConfig::new();
+                    config.operators(true);
+                    let reparsed = TokenParser::from_string_with(&printed, &config);
+                    prop_assert_eq!(Ok(&toks[..]), reparsed.as_ref().map(|parser| parser.tokens()));
+                }
+            }
+
+            #[test]
+            fn one_span_per_token(pat in r"[ab.^$|()*+?\[\]\-\\QEW]{0,14}") {
+                if let Ok(parser) = TokenParser::from_string(&pat) {
+                    prop_assert_eq!(parser.tokens.len(), parser.spans().len());
+                    for span in parser.spans() {
+                        prop_assert!(span.start <= span.end && span.end <= pat.len());
+                        prop_assert!(pat.is_char_boundary(span.start) && pat.is_char_boundary(span.end));
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    mod serde {
+        use serde_json;
+
+        use stream::{self, TokenParser};
+
+        #[test]
+        fn json_encoding() {
+            token_use!();
+            let toks = vec![Grouping(Begin(GBegin::Cap { name: Some("n".to_string()) })),
+                Grouping(Begin(GBegin::Any)), Literal(AnyChar), Grouping(End(GEnd::Any)),
+                Grouping(OrDelimiter), Literal(Range { begin: 'a', end: 'z' }),
+                Literal(Class { name: "v".to_string(), ranges: vec![('a', 'a')] }),
+                Literal(Char('"')), Grouping(End(GEnd::Cap))];
+            let expected = concat!(r#"[{"Grouping":{"Begin":{"Cap":{"name":"n"}}}},"#,
+                r#"{"Grouping":{"Begin":"Any"}},{"Literal":"AnyChar"},{"Grouping":{"End":"Any"}},"#,
+                r#"{"Grouping":"OrDelimiter"},{"Literal":{"Range":{"begin":"a","end":"z"}}},"#,
+                r#"{"Literal":{"Class":{"name":"v","ranges":[["a","a"]]}}},"#,
+                r#"{"Literal":{"Char":"\""}},{"Grouping":{"End":"Cap"}}]"#);
+            assert_eq!(expected, serde_json::to_string(&toks).unwrap());
+            assert_eq!(toks, serde_json::from_str::<Vec<stream::Token>>(expected).unwrap());
+        }
+
+        #[test]
+        fn parser_round_trip() {
+            let parser = TokenParser::from_string("a").unwrap();
+            let json = serde_json::to_string(&parser).unwrap();
+            assert_eq!(concat!(r#"{"pattern_source":"a","#,
+                r#""tokens":[{"Grouping":{"Begin":"Pat"}},{"Literal":{"Char":"a"}},{"Grouping":{"End":"Pat"}}],"#,
+                r#""spans":[{"start":0,"end":1},{"start":0,"end":1},{"start":0,"end":1}]}"#), json);
+
+            let parser = TokenParser::from_glob("src/**/{a,b}[!x].rs").unwrap();
+            let json = serde_json::to_string(&parser).unwrap();
+            assert_eq!(parser, serde_json::from_str(&json).unwrap());
+        }
+
+        #[test]
+        fn parser_checked_on_decode() {
+            let tokens = r#"[{"Grouping":{"Begin":"Pat"}},{"Literal":{"Char":"a"}},{"Grouping":{"End":"Pat"}}]"#;
+            let decode = |source: &str, tokens: &str, spans: &str| {
+                serde_json::from_str::<TokenParser>(&format!(
+                    r#"{{"pattern_source":"{}","tokens":{},"spans":{}}}"#, source, tokens, spans))
+            };
+            let span = r#"{"start":0,"end":1}"#;
+            let spans = format!("[{},{},{}]", span, span, span);
+            assert!(decode("a", tokens, &spans).is_ok());
+            // A span missing
+            assert!(decode("a", tokens, &format!("[{},{}]", span, span)).is_err());
+            // No End(Pat)
+            let unclosed = r#"[{"Grouping":{"Begin":"Pat"}},{"Literal":{"Char":"a"}},{"Literal":{"Char":"b"}}]"#;
+            assert!(decode("a", unclosed, &spans).is_err());
+            // Past the end of the source
+            let long = r#"{"start":0,"end":2}"#;
+            assert!(decode("a", tokens, &format!("[{},{},{}]", span, long, span)).is_err());
+            // Inside a multi-byte char
+            assert!(decode("é", tokens, &spans).is_err());
+        }
+    }
 }
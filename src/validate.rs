@@ -0,0 +1,256 @@
+//! Checks that a token stream is one the rest of the crate can use.
+//!
+//! A well-formed stream is a single `Pat` group in which
+//!
+//! * every `Begin` is closed by the `End` of the same kind, innermost first,
+//! * `OrDelimiter` only separates the branches of an `Or` group, and
+//!   `AndDelimiter` the operands of an `And` group,
+//! * `Maybe`, `Always` and `Any` contain exactly one item, either a literal
+//!   or a whole group,
+//! * every `Range`, and every range of a `Class`, has its begin before or
+//!   at its end.
+
+use std::error::Error;
+use std::fmt;
+
+use stream::{GBegin, GEnd, Token};
+
+/// Why a token stream is malformed, and the index of the token at fault
+#[derive(Clone, PartialEq, Debug)]
+pub struct TokenError {
+    pub index: usize,
+    pub kind: TokenErrorKind,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum TokenErrorKind {
+    /// The stream doesn't start with `Begin(Pat)`
+    NoPat,
+    /// A `Begin(Pat)` inside the pattern
+    NestedPat,
+    /// A token after the `End(Pat)`
+    AfterPat,
+    /// A group that is never closed. The index is that of its `Begin`.
+    Unclosed(GBegin),
+    /// An `End` that doesn't close the innermost open group
+    Mismatched { open: GBegin, end: GEnd },
+    /// An `OrDelimiter` whose innermost group isn't an `Or`
+    DelimiterOutsideOr,
+    /// An `AndDelimiter` whose innermost group isn't an `And`
+    DelimiterOutsideAnd,
+    /// A quantifier holding some number of items other than one. The index
+    /// is that of its `Begin`.
+    Operands(usize),
+    /// A `Range`, or a range of a `Class`, whose begin is after its end
+    BackwardsRange { begin: char, end: char },
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            TokenErrorKind::NoPat => write!(f, "Token stream doesn't start with Begin(Pat)"),
+            TokenErrorKind::NestedPat => write!(f, "Begin(Pat) inside the pattern"),
+            TokenErrorKind::AfterPat => write!(f, "Token after the end of the pattern"),
+            TokenErrorKind::Unclosed(ref open) => write!(f, "{:?} group is never closed", open),
+            TokenErrorKind::Mismatched { ref open, ref end } =>
+                write!(f, "End({:?}) closes a {:?} group", end, open),
+            TokenErrorKind::DelimiterOutsideOr => write!(f, "OrDelimiter outside of an Or group"),
+            TokenErrorKind::DelimiterOutsideAnd =>
+                write!(f, "AndDelimiter outside of an And group"),
+            TokenErrorKind::Operands(count) =>
+                write!(f, "Quantifier has {} items instead of one", count),
+            TokenErrorKind::BackwardsRange { begin, end } =>
+                write!(f, "Range {}-{} is backwards", begin, end),
+        }?;
+        write!(f, " at token {}", self.index)
+    }
+}
+
+impl Error for TokenError {}
+
+impl From<TokenError> for String {
+    fn from(err: TokenError) -> String {
+        err.to_string()
+    }
+}
+
+/// A group that is still open, with the number of items read in it so far
+struct Open<'a> {
+    begin: &'a GBegin,
+    index: usize,
+    items: usize,
+}
+
+fn closes(begin: &GBegin, end: &GEnd) -> bool {
+    matches!((begin, end),
+        (&GBegin::Pat, &GEnd::Pat) | (&GBegin::Cap { .. }, &GEnd::Cap)
+        | (&GBegin::Not, &GEnd::Not) | (&GBegin::Maybe, &GEnd::Maybe)
+        | (&GBegin::Always, &GEnd::Always) | (&GBegin::Any, &GEnd::Any)
+        | (&GBegin::Or, &GEnd::Or) | (&GBegin::Complement, &GEnd::Complement)
+        | (&GBegin::And, &GEnd::And))
+}
+
+/// Checks `toks` against the rules in the module documentation, returning
+/// the first violation
+pub fn validate(toks: &[Token]) -> Result<(), TokenError> {
+    token_use!();
+    let error = |index, kind| Err(TokenError { index, kind });
+
+    match toks.first() {
+        Some(&Grouping(Begin(GBegin::Pat))) => (),
+        _ => return error(0, TokenErrorKind::NoPat),
+    }
+    let mut open = vec![Open { begin: &GBegin::Pat, index: 0, items: 0 }];
+
+    for (index, tok) in toks.iter().enumerate().skip(1) {
+        let parent = match open.last_mut() {
+            Some(parent) => parent,
+            None => return error(index, TokenErrorKind::AfterPat),
+        };
+
+        match *tok {
+            Grouping(Begin(GBegin::Pat)) => return error(index, TokenErrorKind::NestedPat),
+            Grouping(Begin(ref begin)) => {
+                parent.items += 1;
+                open.push(Open { begin, index, items: 0 });
+            },
+            Grouping(End(ref end)) => {
+                if !closes(parent.begin, end) {
+                    return error(index, TokenErrorKind::Mismatched {
+                        open: parent.begin.clone(),
+                        end: end.clone(),
+                    });
+                }
+                match *parent.begin {
+                    GBegin::Maybe | GBegin::Always | GBegin::Any if parent.items != 1 => {
+                        return error(parent.index, TokenErrorKind::Operands(parent.items));
+                    },
+                    _ => (),
+                }
+                open.pop();
+            },
+            Grouping(OrDelimiter) => {
+                if *parent.begin != GBegin::Or {
+                    return error(index, TokenErrorKind::DelimiterOutsideOr);
+                }
+            },
+            Grouping(AndDelimiter) => {
+                if *parent.begin != GBegin::And {
+                    return error(index, TokenErrorKind::DelimiterOutsideAnd);
+                }
+            },
+            Literal(Range { begin, end }) if begin > end => {
+                return error(index, TokenErrorKind::BackwardsRange { begin, end });
+            },
+            Literal(Class { ref ranges, .. }) => {
+                if let Some(&(begin, end)) = ranges.iter().find(|&&(begin, end)| begin > end) {
+                    return error(index, TokenErrorKind::BackwardsRange { begin, end });
+                }
+                parent.items += 1;
+            },
+            Literal(_) => parent.items += 1,
+        }
+    }
+
+    match open.pop() {
+        Some(unclosed) => error(unclosed.index, TokenErrorKind::Unclosed(unclosed.begin.clone())),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use stream::{GBegin, GEnd, TokenParser};
+    use validate::{validate, TokenError, TokenErrorKind};
+
+    fn error_at(index: usize, kind: TokenErrorKind) -> Result<(), TokenError> {
+        Err(TokenError { index, kind })
+    }
+
+    #[test]
+    fn front_ends_are_valid() {
+        for pat in &["", "a(:<n>b|c)*d", r"[^a-z[:digit:]]+\W?", "a||", "(a*)?"] {
+            assert_eq!(Ok(()), validate(TokenParser::from_string(pat).unwrap().tokens()));
+        }
+        assert_eq!(Ok(()), validate(TokenParser::from_glob("**/a/*.{c,h}").unwrap().tokens()));
+        assert_eq!(Ok(()), validate(TokenParser::from_like("a%_", None).unwrap().tokens()));
+        let similar = TokenParser::from_similar_to("(ab){1,3}c{2,}", None).unwrap();
+        assert_eq!(Ok(()), validate(similar.tokens()));
+    }
+
+    #[test]
+    fn nesting() {
+        token_use!();
+        assert_eq!(error_at(0, TokenErrorKind::NoPat), validate(&[]));
+        assert_eq!(error_at(0, TokenErrorKind::NoPat), validate(&[Literal(Char('a'))]));
+        assert_eq!(error_at(2, TokenErrorKind::Mismatched { open: GBegin::Any, end: GEnd::Maybe }),
+            validate(&[Grouping(Begin(GBegin::Pat)), Grouping(Begin(GBegin::Any)),
+                Grouping(End(GEnd::Maybe)), Grouping(End(GEnd::Pat))]));
+        assert_eq!(error_at(1, TokenErrorKind::Unclosed(GBegin::Cap { name: None })),
+            validate(&[Grouping(Begin(GBegin::Pat)), Grouping(Begin(GBegin::Cap { name: None })),
+                Literal(Char('a'))]));
+        assert_eq!(error_at(2, TokenErrorKind::AfterPat),
+            validate(&[Grouping(Begin(GBegin::Pat)), Grouping(End(GEnd::Pat)),
+                Literal(Char('a'))]));
+        assert_eq!(error_at(1, TokenErrorKind::NestedPat),
+            validate(&[Grouping(Begin(GBegin::Pat)), Grouping(Begin(GBegin::Pat)),
+                Grouping(End(GEnd::Pat)), Grouping(End(GEnd::Pat))]));
+    }
+
+    #[test]
+    fn delimiters_and_operands() {
+        token_use!();
+        assert_eq!(error_at(3, TokenErrorKind::DelimiterOutsideOr),
+            validate(&[Grouping(Begin(GBegin::Pat)), Grouping(Begin(GBegin::Cap { name: None })),
+                Literal(Char('a')), Grouping(OrDelimiter), Literal(Char('b')),
+                Grouping(End(GEnd::Cap)), Grouping(End(GEnd::Pat))]));
+        assert_eq!(error_at(2, TokenErrorKind::Operands(2)),
+            validate(&[Grouping(Begin(GBegin::Pat)), Literal(Char('x')),
+                Grouping(Begin(GBegin::Maybe)), Literal(Char('a')), Literal(Char('b')),
+                Grouping(End(GEnd::Maybe)), Grouping(End(GEnd::Pat))]));
+        assert_eq!(error_at(1, TokenErrorKind::Operands(0)),
+            validate(&[Grouping(Begin(GBegin::Pat)), Grouping(Begin(GBegin::Any)),
+                Grouping(End(GEnd::Any)), Grouping(End(GEnd::Pat))]));
+        assert_eq!(error_at(2, TokenErrorKind::BackwardsRange { begin: 'z', end: 'a' }),
+            validate(&[Grouping(Begin(GBegin::Pat)), Grouping(Begin(GBegin::Or)),
+                Literal(Range { begin: 'z', end: 'a' }), Grouping(End(GEnd::Or)),
+                Grouping(End(GEnd::Pat))]));
+        let class = Literal(Class { name: "c".to_string(), ranges: vec![('a', 'b'), ('y', 'x')] });
+        assert_eq!(error_at(1, TokenErrorKind::BackwardsRange { begin: 'y', end: 'x' }),
+            validate(&[Grouping(Begin(GBegin::Pat)), class, Grouping(End(GEnd::Pat))]));
+        assert_eq!(error_at(3, TokenErrorKind::DelimiterOutsideAnd),
+            validate(&[Grouping(Begin(GBegin::Pat)), Grouping(Begin(GBegin::Or)),
+                Literal(Char('a')), Grouping(AndDelimiter), Literal(Char('b')),
+                Grouping(End(GEnd::Or)), Grouping(End(GEnd::Pat))]));
+    }
+
+    #[test]
+    fn from_tokens() {
+        token_use!();
+        let toks = vec![Grouping(Begin(GBegin::Pat)), Literal(Char('a')),
+            Grouping(End(GEnd::Pat))];
+        let parser = TokenParser::from_tokens(toks.clone()).unwrap();
+        assert_eq!(toks, parser.tokens());
+
+        let err = TokenParser::from_tokens(toks[..2].to_vec()).unwrap_err();
+        assert_eq!("Pat group is never closed at token 0", err.to_string());
+    }
+
+    #[test]
+    fn parser_rejects_backwards_ranges() {
+        // The validator refuses them, so the parser mustn't produce them
+        assert_eq!(Err("Range z-a is backwards".to_string()), TokenParser::from_string("[z-a]"));
+        assert!(TokenParser::from_string("[a-a]").is_ok());
+    }
+
+    proptest! {
+        #[test]
+        fn parsed_patterns_are_valid(pat in r"[ab.^$|()*+?\[\]\-\\dW]{0,14}") {
+            if let Ok(parser) = TokenParser::from_string(&pat) {
+                prop_assert_eq!(Ok(()), validate(parser.tokens()));
+            }
+        }
+    }
+}
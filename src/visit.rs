@@ -0,0 +1,312 @@
+//! Walking a token stream with callbacks, so that passes don't have to
+//! track nesting by hand.
+//!
+//! `walk` calls the `enter_` method of a `Visitor` at each `Begin` token, the
+//! matching `exit_` method at its `End`, and `literal`, `or_delimiter` and
+//! `and_delimiter` for the tokens in between. Every callback gets the index
+//! of its token, which is also an index into `TokenParser::spans`. Streams
+//! are validated first, so an `exit_` always has an `enter_` before it.
+//!
+//! ```
+//! use toy_regex::stream::{Literal, TokenParser};
+//! use toy_regex::visit::{walk, Visitor};
+//!
+//! struct Digits(usize);
+//!
+//! impl Visitor for Digits {
+//!     fn literal(&mut self, lit: &Literal, _index: usize) {
+//!         if *lit == Literal::Digit {
+//!             self.0 += 1;
+//!         }
+//!     }
+//! }
+//!
+//! let mut digits = Digits(0);
+//! walk(TokenParser::from_string(r"\d+-\d").unwrap().tokens(), &mut digits).unwrap();
+//! assert_eq!(2, digits.0);
+//! ```
+
+use ast::Repetition;
+use stream::{GBegin, Literal, Token};
+use validate::{validate, TokenError};
+
+/// Callbacks for `walk`. They all do nothing by default.
+pub trait Visitor {
+    fn enter_pat(&mut self, index: usize) {}
+    fn exit_pat(&mut self, index: usize) {}
+    fn enter_capture(&mut self, name: Option<&str>, index: usize) {}
+    fn exit_capture(&mut self, name: Option<&str>, index: usize) {}
+    fn enter_not(&mut self, index: usize) {}
+    fn exit_not(&mut self, index: usize) {}
+    /// `Maybe`, `Always` and `Any`
+    fn enter_repetition(&mut self, kind: Repetition, index: usize) {}
+    fn exit_repetition(&mut self, kind: Repetition, index: usize) {}
+    fn enter_or(&mut self, index: usize) {}
+    fn or_delimiter(&mut self, index: usize) {}
+    fn exit_or(&mut self, index: usize) {}
+    fn enter_complement(&mut self, index: usize) {}
+    fn exit_complement(&mut self, index: usize) {}
+    fn enter_and(&mut self, index: usize) {}
+    fn and_delimiter(&mut self, index: usize) {}
+    fn exit_and(&mut self, index: usize) {}
+    fn literal(&mut self, lit: &Literal, index: usize) {}
+}
+
+/// Callbacks for `walk_mut`, which can change capture names and literals
+/// but not the shape of the stream.
+pub trait VisitorMut {
+    fn enter_pat(&mut self, index: usize) {}
+    fn exit_pat(&mut self, index: usize) {}
+    fn enter_capture(&mut self, name: &mut Option<String>, index: usize) {}
+    fn exit_capture(&mut self, name: Option<&str>, index: usize) {}
+    fn enter_not(&mut self, index: usize) {}
+    fn exit_not(&mut self, index: usize) {}
+    /// `Maybe`, `Always` and `Any`
+    fn enter_repetition(&mut self, kind: Repetition, index: usize) {}
+    fn exit_repetition(&mut self, kind: Repetition, index: usize) {}
+    fn enter_or(&mut self, index: usize) {}
+    fn or_delimiter(&mut self, index: usize) {}
+    fn exit_or(&mut self, index: usize) {}
+    fn enter_complement(&mut self, index: usize) {}
+    fn exit_complement(&mut self, index: usize) {}
+    fn enter_and(&mut self, index: usize) {}
+    fn and_delimiter(&mut self, index: usize) {}
+    fn exit_and(&mut self, index: usize) {}
+    fn literal(&mut self, lit: &mut Literal, index: usize) {}
+}
+
+fn repetition(begin: &GBegin) -> Option<Repetition> {
+    match *begin {
+        GBegin::Maybe => Some(Repetition::Maybe),
+        GBegin::Always => Some(Repetition::Always),
+        GBegin::Any => Some(Repetition::Any),
+        _ => None,
+    }
+}
+
+/// Calls `visitor` for every token of `toks`, after checking that they are
+/// well-formed
+pub fn walk<V: Visitor + ?Sized>(toks: &[Token], visitor: &mut V) -> Result<(), TokenError> {
+    token_use!();
+    validate(toks)?;
+    let mut open = Vec::new();
+
+    for (index, tok) in toks.iter().enumerate() {
+        let (begin, entering) = match *tok {
+            Literal(ref lit) => {
+                visitor.literal(lit, index);
+                continue;
+            },
+            Grouping(OrDelimiter) => {
+                visitor.or_delimiter(index);
+                continue;
+            },
+            Grouping(AndDelimiter) => {
+                visitor.and_delimiter(index);
+                continue;
+            },
+            Grouping(Begin(ref begin)) => {
+                open.push(begin);
+                (begin, true)
+            },
+            Grouping(End(_)) => (open.pop().unwrap(), false),
+        };
+
+        match (begin, entering) {
+            (&GBegin::Pat, true) => visitor.enter_pat(index),
+            (&GBegin::Pat, false) => visitor.exit_pat(index),
+            (GBegin::Cap { name }, true) => visitor.enter_capture(name.as_deref(), index),
+            (GBegin::Cap { name }, false) => visitor.exit_capture(name.as_deref(), index),
+            (&GBegin::Not, true) => visitor.enter_not(index),
+            (&GBegin::Not, false) => visitor.exit_not(index),
+            (&GBegin::Or, true) => visitor.enter_or(index),
+            (&GBegin::Or, false) => visitor.exit_or(index),
+            (&GBegin::Complement, true) => visitor.enter_complement(index),
+            (&GBegin::Complement, false) => visitor.exit_complement(index),
+            (&GBegin::And, true) => visitor.enter_and(index),
+            (&GBegin::And, false) => visitor.exit_and(index),
+            (kind, true) => visitor.enter_repetition(repetition(kind).unwrap(), index),
+            (kind, false) => visitor.exit_repetition(repetition(kind).unwrap(), index),
+        }
+    }
+    Ok(())
+}
+
+/// Calls `visitor` for every token of `toks`, after checking that they are
+/// well-formed
+pub fn walk_mut<V: VisitorMut + ?Sized>(toks: &mut [Token], visitor: &mut V)
+    -> Result<(), TokenError> {
+    token_use!();
+    validate(toks)?;
+    // Indices of the open `Begin`s, since `toks` can't stay borrowed
+    let mut open = Vec::new();
+
+    for index in 0..toks.len() {
+        match toks[index] {
+            Literal(ref mut lit) => visitor.literal(lit, index),
+            Grouping(OrDelimiter) => visitor.or_delimiter(index),
+            Grouping(AndDelimiter) => visitor.and_delimiter(index),
+            Grouping(Begin(ref mut begin)) => {
+                open.push(index);
+                match *begin {
+                    GBegin::Pat => visitor.enter_pat(index),
+                    GBegin::Cap { ref mut name } => visitor.enter_capture(name, index),
+                    GBegin::Not => visitor.enter_not(index),
+                    GBegin::Or => visitor.enter_or(index),
+                    GBegin::Complement => visitor.enter_complement(index),
+                    GBegin::And => visitor.enter_and(index),
+                    ref kind => visitor.enter_repetition(repetition(kind).unwrap(), index),
+                }
+            },
+            Grouping(End(_)) => {
+                let begin = match toks[open.pop().unwrap()] {
+                    Grouping(Begin(ref begin)) => begin,
+                    _ => unreachable!(),
+                };
+                match *begin {
+                    GBegin::Pat => visitor.exit_pat(index),
+                    GBegin::Cap { ref name } => visitor.exit_capture(name.as_deref(), index),
+                    GBegin::Not => visitor.exit_not(index),
+                    GBegin::Or => visitor.exit_or(index),
+                    GBegin::Complement => visitor.exit_complement(index),
+                    GBegin::And => visitor.exit_and(index),
+                    ref kind => visitor.exit_repetition(repetition(kind).unwrap(), index),
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+struct Captures(Vec<Option<String>>);
+
+impl Visitor for Captures {
+    fn enter_capture(&mut self, name: Option<&str>, index: usize) {
+        self.0.push(name.map(str::to_string));
+    }
+}
+
+/// The names of the capture groups in the order they open, `None` for
+/// unnamed ones
+pub fn captures(toks: &[Token]) -> Result<Vec<Option<String>>, TokenError> {
+    let mut captures = Captures(Vec::new());
+    walk(toks, &mut captures)?;
+    Ok(captures.0)
+}
+
+#[derive(Default)]
+struct Depth {
+    current: usize,
+    max: usize,
+}
+
+impl Depth {
+    fn enter(&mut self) {
+        self.current += 1;
+        self.max = self.max.max(self.current);
+    }
+}
+
+impl Visitor for Depth {
+    fn enter_capture(&mut self, name: Option<&str>, index: usize) { self.enter() }
+    fn exit_capture(&mut self, name: Option<&str>, index: usize) { self.current -= 1 }
+    fn enter_not(&mut self, index: usize) { self.enter() }
+    fn exit_not(&mut self, index: usize) { self.current -= 1 }
+    fn enter_repetition(&mut self, kind: Repetition, index: usize) { self.enter() }
+    fn exit_repetition(&mut self, kind: Repetition, index: usize) { self.current -= 1 }
+    fn enter_or(&mut self, index: usize) { self.enter() }
+    fn exit_or(&mut self, index: usize) { self.current -= 1 }
+    fn enter_complement(&mut self, index: usize) { self.enter() }
+    fn exit_complement(&mut self, index: usize) { self.current -= 1 }
+    fn enter_and(&mut self, index: usize) { self.enter() }
+    fn exit_and(&mut self, index: usize) { self.current -= 1 }
+}
+
+/// How deeply groups nest inside the `Pat` group, 0 if there are none
+pub fn depth(toks: &[Token]) -> Result<usize, TokenError> {
+    let mut depth = Depth::default();
+    walk(toks, &mut depth)?;
+    Ok(depth.max)
+}
+
+#[cfg(test)]
+mod tests {
+    use ast::Repetition;
+    use stream::{Literal, TokenParser};
+    use validate::TokenErrorKind;
+    use visit::{captures, depth, walk, walk_mut, Visitor, VisitorMut};
+
+    fn tokens(pat: &str) -> Vec<::stream::Token> {
+        TokenParser::from_string(pat).unwrap().into_tokens()
+    }
+
+    /// Records every callback
+    struct Trace(Vec<String>);
+
+    impl Visitor for Trace {
+        fn enter_pat(&mut self, index: usize) { self.0.push(format!("pat {}", index)) }
+        fn exit_pat(&mut self, index: usize) { self.0.push(format!("/pat {}", index)) }
+        fn enter_capture(&mut self, name: Option<&str>, index: usize) {
+            self.0.push(format!("cap {:?} {}", name, index))
+        }
+        fn exit_capture(&mut self, name: Option<&str>, index: usize) {
+            self.0.push(format!("/cap {:?} {}", name, index))
+        }
+        fn enter_repetition(&mut self, kind: Repetition, index: usize) {
+            self.0.push(format!("{:?} {}", kind, index))
+        }
+        fn exit_repetition(&mut self, kind: Repetition, index: usize) {
+            self.0.push(format!("/{:?} {}", kind, index))
+        }
+        fn enter_or(&mut self, index: usize) { self.0.push(format!("or {}", index)) }
+        fn or_delimiter(&mut self, index: usize) { self.0.push(format!("| {}", index)) }
+        fn exit_or(&mut self, index: usize) { self.0.push(format!("/or {}", index)) }
+        fn literal(&mut self, lit: &Literal, index: usize) {
+            self.0.push(format!("{:?} {}", lit, index))
+        }
+    }
+
+    #[test]
+    fn callback_order() {
+        let mut trace = Trace(Vec::new());
+        walk(&tokens("(:<n>a|b)*"), &mut trace).unwrap();
+        assert_eq!(vec!["pat 0", "Any 1", "cap Some(\"n\") 2", "or 3", "Char('a') 4", "| 5",
+            "Char('b') 6", "/or 7", "/cap Some(\"n\") 8", "/Any 9", "/pat 10"], trace.0);
+    }
+
+    #[test]
+    fn malformed_streams_are_rejected() {
+        let mut toks = tokens("(a)");
+        toks.pop();
+        let err = walk(&toks, &mut Trace(Vec::new())).unwrap_err();
+        assert_eq!(TokenErrorKind::Unclosed(::stream::GBegin::Pat), err.kind);
+    }
+
+    #[test]
+    fn mutable_visitor() {
+        struct Upper;
+
+        impl VisitorMut for Upper {
+            fn enter_capture(&mut self, name: &mut Option<String>, index: usize) {
+                *name = name.as_ref().map(|name| name.to_uppercase());
+            }
+            fn literal(&mut self, lit: &mut Literal, index: usize) {
+                if let Literal::Char(ref mut c) = *lit {
+                    *c = c.to_ascii_uppercase();
+                }
+            }
+        }
+
+        let mut toks = tokens("(:<x>ab)c|[de]");
+        walk_mut(&mut toks, &mut Upper).unwrap();
+        assert_eq!(tokens("(:<X>AB)C|[DE]"), toks);
+    }
+
+    #[test]
+    fn built_in_analyses() {
+        assert_eq!(vec![None, Some("b".to_string()), None],
+            captures(&tokens("(a(:<b>c))(d)")).unwrap());
+        assert_eq!(0, depth(&tokens("abc")).unwrap());
+        assert_eq!(4, depth(&tokens(r"a(b|\W)*")).unwrap());
+    }
+}